@@ -0,0 +1,222 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::DriverConfig;
+
+/// Tells apart the temporary directories of concurrent stores within one driver
+static STORES: AtomicU64 = AtomicU64::new(0);
+
+/// On-disk LRU cache of compiled artifacts (`run`, `run.jar`, ...).
+///
+/// Every entry lives in `{dir}/{key}/` and its modification time is refreshed on each
+/// hit, so the least recently used entries are evicted once the cache outgrows `max_size`.
+/// Entries are written to a temporary directory first and renamed into place, which keeps
+/// concurrent workers from ever seeing a half written artifact.
+pub struct CompileCache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl CompileCache {
+    pub fn new(dir: PathBuf, max_size: u64) -> Self {
+        CompileCache { dir, max_size }
+    }
+
+    pub fn from_config(config: &DriverConfig) -> Option<Self> {
+        config
+            .compile_cache_dir
+            .as_ref()
+            .map(|dir| CompileCache::new(PathBuf::from(dir), config.compile_cache_max_size))
+    }
+
+    /// Hash of every regular file in the game directory (boilerplate and player code)
+    /// together with whatever else decides the compiler output, e.g. the image digest and
+    /// the compile command
+    pub fn key(game_dir: &Path, extra: &[&str]) -> io::Result<String> {
+        let mut files = vec![];
+        collect_files(game_dir, game_dir, &mut files)?;
+        files.sort();
+
+        let mut hasher = Sha256::new();
+        for relative in files {
+            let mut contents = vec![];
+            File::open(game_dir.join(&relative))?.read_to_end(&mut contents)?;
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update([0]);
+            hasher.update((contents.len() as u64).to_le_bytes());
+            hasher.update(&contents);
+        }
+        for part in extra {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect())
+    }
+
+    /// Copies a cached artifact to `dest`, returns false on a miss
+    pub fn fetch(&self, key: &str, dest: &Path) -> io::Result<bool> {
+        let entry = self.dir.join(key);
+        let cached = entry.join(artifact_name(dest)?);
+        if !cached.is_file() {
+            return Ok(false);
+        }
+        // Artifacts like `target/release/run` live below directories only the compiler creates
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&cached, dest)?;
+        File::open(&entry)?.set_modified(SystemTime::now())?;
+        Ok(true)
+    }
+
+    pub fn store(&self, key: &str, artifact: &Path) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let entry = self.dir.join(key);
+        if entry.exists() {
+            return Ok(());
+        }
+
+        let tmp = self.dir.join(format!(
+            ".{key}.{}.{}",
+            std::process::id(),
+            STORES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&tmp)?;
+        fs::copy(artifact, tmp.join(artifact_name(artifact)?))?;
+        if fs::rename(&tmp, &entry).is_err() {
+            // Another worker stored the same key in the meantime
+            let _ = fs::remove_dir_all(&tmp);
+        }
+        self.evict()
+    }
+
+    /// Removes the least recently used entries until the cache fits in `max_size`
+    fn evict(&self) -> io::Result<()> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let modified = entry.metadata()?.modified()?;
+            entries.push((modified, dir_size(&entry.path())?, entry.path()));
+        }
+        entries.sort();
+
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        for (_, size, path) in entries {
+            if total <= self.max_size {
+                break;
+            }
+            fs::remove_dir_all(path)?;
+            total -= size;
+        }
+        Ok(())
+    }
+}
+
+fn artifact_name(path: &Path) -> io::Result<&std::ffi::OsStr> {
+    path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "artifact has no file name"))
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let ty = entry.file_type()?;
+        if ty.is_dir() {
+            collect_files(root, &entry.path(), files)?;
+        } else if ty.is_file() {
+            if let Ok(relative) = entry.path().strip_prefix(root) {
+                files.push(relative.to_path_buf());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        size += entry?.metadata()?.len();
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread, time::Duration};
+
+    use super::CompileCache;
+    use crate::utils::TestDir;
+
+    #[test]
+    fn key_depends_on_files_and_extra() {
+        let game = TestDir::new("cache_key_depends_on_files_and_extra");
+        fs::write(game.join("run.cpp"), "int main() {}").unwrap();
+        fs::create_dir(game.join("include")).unwrap();
+        fs::write(game.join("include/player.h"), "#pragma once").unwrap();
+
+        let key = CompileCache::key(&game, &["sha256:abc"]).unwrap();
+        assert_eq!(key, CompileCache::key(&game, &["sha256:abc"]).unwrap());
+        assert_ne!(key, CompileCache::key(&game, &["sha256:def"]).unwrap());
+
+        fs::write(game.join("include/player.h"), "#pragma once\n").unwrap();
+        assert_ne!(key, CompileCache::key(&game, &["sha256:abc"]).unwrap());
+    }
+
+    #[test]
+    fn store_fetch_and_evict() {
+        let root = TestDir::new("cache_store_fetch_and_evict");
+        let cache = CompileCache::new(root.join("cache"), 10);
+        let game = root.join("game");
+        fs::create_dir(&game).unwrap();
+
+        fs::write(game.join("run"), "123456").unwrap();
+        cache.store("first", &game.join("run")).unwrap();
+        fs::remove_file(game.join("run")).unwrap();
+        assert!(!cache.fetch("second", &game.join("run")).unwrap());
+        assert!(cache.fetch("first", &game.join("run")).unwrap());
+        assert_eq!(fs::read_to_string(game.join("run")).unwrap(), "123456");
+
+        // Storing a second entry goes over the limit, the older one has to go
+        thread::sleep(Duration::from_millis(20));
+        fs::write(game.join("run"), "abcdef").unwrap();
+        cache.store("second", &game.join("run")).unwrap();
+        assert!(!cache.fetch("first", &game.join("run")).unwrap());
+        assert!(cache.fetch("second", &game.join("run")).unwrap());
+    }
+
+    #[test]
+    fn concurrent_stores_of_one_key() {
+        let root = TestDir::new("cache_concurrent_stores_of_one_key");
+        let cache = CompileCache::new(root.join("cache"), 1 << 20);
+        fs::write(root.join("run"), "artifact").unwrap();
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| cache.store("same", &root.join("run")).unwrap());
+            }
+        });
+        let entries = fs::read_dir(root.join("cache"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(entries, ["same"]);
+        assert_eq!(
+            fs::read_to_string(root.join("cache/same/run")).unwrap(),
+            "artifact"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::{write::GzEncoder, Compression};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::response::GameStatus;
+
+/// How `GameResult.log` is sent, chosen per request (`log_encoding`) or by `LOG_ENCODING`.
+/// Compressed logs are base64 encoded so they still fit in the JSON string.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum LogEncoding {
+    #[serde(alias = "plain")]
+    PLAIN,
+    #[serde(alias = "gzip")]
+    GZIP,
+    #[serde(alias = "zstd")]
+    ZSTD,
+}
+
+impl FromStr for LogEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "plain" => Ok(LogEncoding::PLAIN),
+            "gzip" => Ok(LogEncoding::GZIP),
+            "zstd" => Ok(LogEncoding::ZSTD),
+            other => Err(format!(
+                "unknown log encoding {other}, expected plain, gzip or zstd"
+            )),
+        }
+    }
+}
+
+impl Display for LogEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogEncoding::PLAIN => write!(f, "plain"),
+            LogEncoding::GZIP => write!(f, "gzip"),
+            LogEncoding::ZSTD => write!(f, "zstd"),
+        }
+    }
+}
+
+/// `log` compressed with `encoding` and base64 encoded, unchanged for `PLAIN`
+pub fn encode(log: &str, encoding: LogEncoding) -> io::Result<String> {
+    let compressed = match encoding {
+        LogEncoding::PLAIN => return Ok(log.to_owned()),
+        LogEncoding::GZIP => {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(log.as_bytes())?;
+            encoder.finish()?
+        }
+        LogEncoding::ZSTD => zstd::encode_all(log.as_bytes(), 0)?,
+    };
+    Ok(STANDARD.encode(compressed))
+}
+
+/// Encodes the log of the game result in place and names the encoding in the result.
+/// A log that cannot be compressed is sent as plain text rather than failing the game.
+pub fn encode_log(status: &mut GameStatus, encoding: LogEncoding) {
+    let result = match &mut status.game_result {
+        Some(result) if encoding != LogEncoding::PLAIN => result,
+        _ => return,
+    };
+    match encode(&result.log, encoding) {
+        Ok(log) => {
+            result.log = log;
+            result.log_encoding = Some(encoding);
+        }
+        Err(e) => warn!(
+            "Unable to {encoding} the log of game {}: {e}",
+            status.game_id
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use flate2::read::GzDecoder;
+
+    use super::{encode, LogEncoding};
+
+    #[test]
+    fn round_trips() {
+        let log = "TURN, 1\nPRINT, hello\nCOINS, 100\n".repeat(100);
+
+        assert_eq!(encode(&log, LogEncoding::PLAIN).unwrap(), log);
+
+        let gzip = STANDARD
+            .decode(encode(&log, LogEncoding::GZIP).unwrap())
+            .unwrap();
+        let mut decoded = String::new();
+        GzDecoder::new(&gzip[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, log);
+
+        let zstd = STANDARD
+            .decode(encode(&log, LogEncoding::ZSTD).unwrap())
+            .unwrap();
+        assert!(zstd.len() < log.len());
+        assert_eq!(zstd::decode_all(&zstd[..]).unwrap(), log.as_bytes());
+    }
+
+    #[test]
+    fn parses_config_and_request_values() {
+        assert_eq!("GZip".parse::<LogEncoding>(), Ok(LogEncoding::GZIP));
+        assert!("brotli".parse::<LogEncoding>().is_err());
+        assert_eq!(
+            serde_json::from_str::<LogEncoding>(r#""zstd""#).unwrap(),
+            LogEncoding::ZSTD
+        );
+        assert_eq!(
+            serde_json::to_string(&LogEncoding::GZIP).unwrap(),
+            r#""GZIP""#
+        );
+    }
+}
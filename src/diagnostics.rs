@@ -0,0 +1,460 @@
+use crate::response::{Diagnostic, Severity};
+
+/// Where compilers print a file name: at the start of a line (g++, javac, tsc) or after one
+/// of these (Python, rustc, g++ include chains)
+const PATH_MARKERS: [&str; 4] = ["File \"", "--> ", "In file included from ", "from "];
+
+/// Rewrites the container (or host) paths the compiler saw into paths relative to the
+/// player's code, e.g. `/player_code/run.cpp` into `run.cpp`. Only the file name of each line
+/// is touched, the same path in a message or a code snippet stays as it is.
+pub fn relative_paths(output: &str, prefixes: &[String]) -> String {
+    // Longest first, so that a nested mount is not left half stripped
+    let mut prefixes = prefixes
+        .iter()
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<_>>();
+    prefixes.sort_by_key(|p| std::cmp::Reverse(p.len()));
+    output
+        .split_inclusive('\n')
+        .map(|line| relative_path(line, &prefixes))
+        .collect()
+}
+
+fn relative_path(line: &str, prefixes: &[&String]) -> String {
+    let indent = line.len() - line.trim_start().len();
+    let start = PATH_MARKERS
+        .iter()
+        .find(|marker| line[indent..].starts_with(*marker))
+        .map_or(indent, |marker| indent + marker.len());
+    match prefixes
+        .iter()
+        .find(|prefix| line[start..].starts_with(prefix.as_str()))
+    {
+        Some(prefix) => format!("{}{}", &line[..start], &line[start + prefix.len()..]),
+        None => line.to_owned(),
+    }
+}
+
+/// Picks the g++, javac, Python syntax error, rustc and tsc diagnostics out of a compiler's
+/// output. Anything that is not recognised (summaries, `In function ...` lines) is skipped.
+pub fn parse(output: &str) -> Vec<Diagnostic> {
+    let lines = output.lines().collect::<Vec<_>>();
+    let mut diagnostics = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let parsed = parse_rustc(&lines, i)
+            .or_else(|| parse_tsc(&lines, i))
+            .or_else(|| parse_gcc(&lines, i))
+            .or_else(|| parse_javac(&lines, i))
+            .or_else(|| parse_python(&lines, i));
+        match parsed {
+            Some((diagnostic, consumed)) => {
+                diagnostics.push(diagnostic);
+                i += consumed;
+            }
+            None => i += 1,
+        }
+    }
+    diagnostics
+}
+
+fn severity(s: &str) -> Option<Severity> {
+    match s.trim() {
+        "error" | "fatal error" => Some(Severity::ERROR),
+        "warning" => Some(Severity::WARNING),
+        "note" => Some(Severity::NOTE),
+        _ => None,
+    }
+}
+
+/// Compilers started in the code directory may still print `./run.py`
+fn file(path: &str) -> String {
+    path.strip_prefix("./").unwrap_or(path).to_owned()
+}
+
+fn number(s: &str) -> Option<u32> {
+    s.trim().parse().ok()
+}
+
+/// `run.cpp:12:5: error: message`, followed by the `  12 |  code` and `     |  ^` snippet lines
+fn parse_gcc(lines: &[&str], i: usize) -> Option<(Diagnostic, usize)> {
+    let parts = lines[i].splitn(5, ':').collect::<Vec<_>>();
+    if parts.len() != 5 || parts[0].is_empty() {
+        return None;
+    }
+    let line = number(parts[1])?;
+    let column = number(parts[2])?;
+    let severity = severity(parts[3])?;
+
+    let snippet = lines[i + 1..]
+        .iter()
+        .take_while(|l| is_gcc_snippet(l))
+        .copied()
+        .collect::<Vec<_>>();
+    Some((
+        Diagnostic {
+            file: file(parts[0]),
+            line,
+            column: Some(column),
+            severity,
+            message: parts[4].trim().to_owned(),
+            snippet: (!snippet.is_empty()).then(|| snippet.join("\n")),
+        },
+        1 + snippet.len(),
+    ))
+}
+
+fn is_gcc_snippet(line: &str) -> bool {
+    match line.split_once('|') {
+        Some((gutter, _)) => {
+            gutter.starts_with(' ') && gutter.trim().chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// `Run.java:5: error: message`, followed by the offending line and a caret under the column
+fn parse_javac(lines: &[&str], i: usize) -> Option<(Diagnostic, usize)> {
+    let parts = lines[i].splitn(4, ':').collect::<Vec<_>>();
+    if parts.len() != 4 || parts[0].is_empty() {
+        return None;
+    }
+    let line = number(parts[1])?;
+    let severity = severity(parts[2])?;
+
+    let (snippet, column) = match (lines.get(i + 1), lines.get(i + 2)) {
+        (Some(code), Some(caret)) if caret.trim() == "^" => (
+            Some(code.to_string()),
+            caret.find('^').map(|c| c as u32 + 1),
+        ),
+        _ => (None, None),
+    };
+    let consumed = if snippet.is_some() { 3 } else { 1 };
+    Some((
+        Diagnostic {
+            file: file(parts[0]),
+            line,
+            column,
+            severity,
+            message: parts[3].trim().to_owned(),
+            snippet,
+        },
+        consumed,
+    ))
+}
+
+/// ```text
+/// error[E0425]: cannot find value `x` in this scope
+///  --> src/run.rs:3:5
+///   |
+/// 3 |     x
+///   |     ^ not found in this scope
+/// ```
+/// Summaries like `error: aborting due to 1 previous error` have no location and are skipped.
+fn parse_rustc(lines: &[&str], i: usize) -> Option<(Diagnostic, usize)> {
+    let (kind, message) = lines[i].split_once(": ")?;
+    let (severity, code) = match kind.split_once('[') {
+        Some((severity, code)) => (severity, Some(code.strip_suffix(']')?)),
+        None => (kind, None),
+    };
+    let severity = match severity {
+        "error" => Severity::ERROR,
+        "warning" => Severity::WARNING,
+        _ => return None,
+    };
+    let location = lines.get(i + 1)?.trim_start().strip_prefix("--> ")?;
+    let mut parts = location.rsplitn(3, ':');
+    let column = number(parts.next()?)?;
+    let line = number(parts.next()?)?;
+    let path = parts.next()?;
+
+    let snippet = lines[i + 2..]
+        .iter()
+        .take_while(|l| is_rustc_snippet(l))
+        .copied()
+        .collect::<Vec<_>>();
+    Some((
+        Diagnostic {
+            file: file(path),
+            line,
+            column: Some(column),
+            severity,
+            message: match code {
+                Some(code) => format!("{code}: {}", message.trim()),
+                None => message.trim().to_owned(),
+            },
+            snippet: (!snippet.is_empty()).then(|| snippet.join("\n")),
+        },
+        2 + snippet.len(),
+    ))
+}
+
+/// The `  |`, `3 |` and `  = help: ...` lines below a rustc diagnostic
+fn is_rustc_snippet(line: &str) -> bool {
+    if line.trim_start().starts_with("= ") {
+        return true;
+    }
+    match line.split_once('|') {
+        Some((gutter, _)) => gutter.trim().chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// `run.ts(3,5): error TS2322: message`, as printed by `tsc` without `--pretty`
+fn parse_tsc(lines: &[&str], i: usize) -> Option<(Diagnostic, usize)> {
+    let (location, rest) = lines[i].split_once("): ")?;
+    let (path, position) = location.rsplit_once('(')?;
+    let (line, column) = position.split_once(',')?;
+    let (severity, message) = rest.split_once(' ')?;
+    let severity = match severity {
+        "error" => Severity::ERROR,
+        "warning" => Severity::WARNING,
+        "message" => Severity::NOTE,
+        _ => return None,
+    };
+    if path.is_empty() || !message.starts_with("TS") {
+        return None;
+    }
+    Some((
+        Diagnostic {
+            file: file(path),
+            line: number(line)?,
+            column: Some(number(column)?),
+            severity,
+            message: message.trim().to_owned(),
+            snippet: None,
+        },
+        1,
+    ))
+}
+
+/// ```text
+///   File "run.py", line 3
+///     x = = 1
+///         ^
+/// SyntaxError: invalid syntax
+/// ```
+/// Python strips the indentation of the line it prints, so the column is left out.
+fn parse_python(lines: &[&str], i: usize) -> Option<(Diagnostic, usize)> {
+    let location = lines[i].trim().strip_prefix("File \"")?;
+    let (file, rest) = location.split_once('"')?;
+    let line = rest
+        .strip_prefix(", line ")?
+        .split(',')
+        .next()
+        .and_then(number)?;
+
+    // The error itself follows the (optional) code and caret lines
+    let offset = (1..=3).find(|offset| {
+        lines
+            .get(i + offset)
+            .is_some_and(|l| python_error(l).is_some())
+    })?;
+    let message = python_error(lines[i + offset])?;
+    let snippet = lines[i + 1..i + offset]
+        .iter()
+        .find(|l| !l.trim().is_empty() && !l.trim().starts_with('^'))
+        .map(|l| l.trim().to_owned());
+    Some((
+        Diagnostic {
+            file: self::file(file),
+            line,
+            column: None,
+            severity: Severity::ERROR,
+            message,
+            snippet,
+        },
+        offset + 1,
+    ))
+}
+
+fn python_error(line: &str) -> Option<String> {
+    ["SyntaxError", "IndentationError", "TabError"]
+        .iter()
+        .find(|kind| line.starts_with(&format!("{kind}:")))
+        .map(|_| line.trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, relative_paths};
+    use crate::response::{Diagnostic, Severity};
+
+    #[test]
+    fn gcc_diagnostics() {
+        let output = relative_paths(
+            "/player_code/run.cpp: In function 'int main()':\n\
+             /player_code/run.cpp:4:5: error: 'x' was not declared in this scope\n\
+             \x20   4 |     x = 1;\n\
+             \x20     |     ^\n\
+             /player_code/run.cpp:2:1: note: some note\n\
+             compilation terminated.\n",
+            &["/player_code/".to_owned(), "/tmp/1/".to_owned()],
+        );
+        assert_eq!(
+            parse(&output),
+            vec![
+                Diagnostic {
+                    file: "run.cpp".to_owned(),
+                    line: 4,
+                    column: Some(5),
+                    severity: Severity::ERROR,
+                    message: "'x' was not declared in this scope".to_owned(),
+                    snippet: Some("    4 |     x = 1;\n      |     ^".to_owned()),
+                },
+                Diagnostic {
+                    file: "run.cpp".to_owned(),
+                    line: 2,
+                    column: Some(1),
+                    severity: Severity::NOTE,
+                    message: "some note".to_owned(),
+                    snippet: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn javac_diagnostics() {
+        let output = "/player_code/Run.java:5: error: ';' expected\n\
+                      \x20       int x = 1\n\
+                      \x20                ^\n\
+                      1 error\n";
+        assert_eq!(
+            parse(&relative_paths(output, &["/player_code/".to_owned()])),
+            vec![Diagnostic {
+                file: "Run.java".to_owned(),
+                line: 5,
+                column: Some(18),
+                severity: Severity::ERROR,
+                message: "';' expected".to_owned(),
+                snippet: Some("        int x = 1".to_owned()),
+            }]
+        );
+    }
+
+    #[test]
+    fn python_syntax_errors() {
+        let output = "*** Error compiling './run.py'...\n\
+                      \x20 File \"./run.py\", line 3\n\
+                      \x20   x = = 1\n\
+                      \x20       ^\n\
+                      SyntaxError: invalid syntax\n";
+        assert_eq!(
+            parse(output),
+            vec![Diagnostic {
+                file: "run.py".to_owned(),
+                line: 3,
+                column: None,
+                severity: Severity::ERROR,
+                message: "SyntaxError: invalid syntax".to_owned(),
+                snippet: Some("x = = 1".to_owned()),
+            }]
+        );
+    }
+
+    #[test]
+    fn rustc_diagnostics() {
+        let output = "error[E0425]: cannot find value `x` in this scope\n\
+                      \x20--> src/run.rs:3:5\n\
+                      \x20 |\n\
+                      3 |     x\n\
+                      \x20 |     ^ not found in this scope\n\
+                      \n\
+                      warning: unused variable: `y`\n\
+                      \x20--> src/run.rs:2:9\n\
+                      \x20 |\n\
+                      2 |     let y = 1;\n\
+                      \x20 |         ^ help: prefix it with an underscore: `_y`\n\
+                      \x20 |\n\
+                      \x20 = note: `#[warn(unused_variables)]` on by default\n\
+                      \n\
+                      error: aborting due to 1 previous error\n";
+        assert_eq!(
+            parse(output),
+            vec![
+                Diagnostic {
+                    file: "src/run.rs".to_owned(),
+                    line: 3,
+                    column: Some(5),
+                    severity: Severity::ERROR,
+                    message: "E0425: cannot find value `x` in this scope".to_owned(),
+                    snippet: Some("  |\n3 |     x\n  |     ^ not found in this scope".to_owned()),
+                },
+                Diagnostic {
+                    file: "src/run.rs".to_owned(),
+                    line: 2,
+                    column: Some(9),
+                    severity: Severity::WARNING,
+                    message: "unused variable: `y`".to_owned(),
+                    snippet: Some(
+                        "  |\n2 |     let y = 1;\n  |         ^ help: prefix it with an \
+                         underscore: `_y`\n  |\n  = note: `#[warn(unused_variables)]` on by \
+                         default"
+                            .to_owned()
+                    ),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tsc_diagnostics() {
+        let output = relative_paths(
+            "/player_code/run.ts(3,5): error TS2322: Type 'string' is not assignable to type \
+             'number'.\n\
+             run.ts(10,1): error TS1005: '}' expected.\n",
+            &["/player_code/".to_owned()],
+        );
+        assert_eq!(
+            parse(&output),
+            vec![
+                Diagnostic {
+                    file: "run.ts".to_owned(),
+                    line: 3,
+                    column: Some(5),
+                    severity: Severity::ERROR,
+                    message: "TS2322: Type 'string' is not assignable to type 'number'.".to_owned(),
+                    snippet: None,
+                },
+                Diagnostic {
+                    file: "run.ts".to_owned(),
+                    line: 10,
+                    column: Some(1),
+                    severity: Severity::ERROR,
+                    message: "TS1005: '}' expected.".to_owned(),
+                    snippet: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn only_file_names_are_made_relative() {
+        let prefixes = ["/player_code/".to_owned(), "/tmp/1/".to_owned()];
+        assert_eq!(
+            relative_paths(
+                "In file included from /player_code/run.cpp:1:\n\
+                 /player_code/player.h:2:5: error: '/player_code/' is no type\n\
+                 \x20   2 |     \"/player_code/\" x;\n\
+                 \x20 File \"/player_code/run.py\", line 3\n\
+                 \x20 --> /player_code/src/run.rs:3:5\n",
+                &prefixes,
+            ),
+            "In file included from run.cpp:1:\n\
+             player.h:2:5: error: '/player_code/' is no type\n\
+             \x20   2 |     \"/player_code/\" x;\n\
+             \x20 File \"run.py\", line 3\n\
+             \x20 --> src/run.rs:3:5\n"
+        );
+    }
+
+    #[test]
+    fn runtime_tracebacks_are_not_diagnostics() {
+        let output = "Traceback (most recent call last):\n\
+                      \x20 File \"run.py\", line 3, in <module>\n\
+                      \x20   main()\n\
+                      NameError: name 'main' is not defined\n";
+        assert!(parse(output).is_empty());
+    }
+}
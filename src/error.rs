@@ -6,6 +6,9 @@ pub enum SimulatorError {
     FifoCreationError(String),
     EpollError(String),
     TimeOutError(String),
+    MemoryLimitExceeded(String),
+    CpuTimeLimitExceeded(String),
+    ShutdownError(String),
 }
 
 #[derive(Debug)]
@@ -24,3 +27,24 @@ impl From<EpollError> for SimulatorError {
         SimulatorError::EpollError(format!("{val:?}"))
     }
 }
+
+#[derive(Debug)]
+pub enum ConfigError {
+    FileError(String),
+    Invalid(Vec<String>),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::FileError(e) => write!(f, "{e}"),
+            ConfigError::Invalid(problems) => {
+                writeln!(f, "Invalid driver configuration:")?;
+                for problem in problems {
+                    writeln!(f, "  - {problem}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
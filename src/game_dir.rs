@@ -1,13 +1,27 @@
+use std::path::Path;
+
+use crate::sandbox::lifecycle::{attempt_suffix, parse_attempt};
+
+/// Directory of one attempt at a game, `/tmp/{game_id}-{attempt_suffix}`, see
+/// `lifecycle::attempt_suffix`.
 pub struct GameDir {
     full_path: String,
 }
 
 impl GameDir {
     pub fn new(game_id: &str) -> Option<Self> {
-        std::fs::create_dir(format!("/tmp/{game_id}")).ok()?;
-        Some(GameDir {
-            full_path: format!("/tmp/{game_id}"),
-        })
+        let full_path = format!("/tmp/{game_id}-{}", attempt_suffix());
+        std::fs::create_dir(&full_path).ok()?;
+        Some(GameDir { full_path })
+    }
+    /// Removes what earlier attempts at the game left behind when their driver died. Returns
+    /// how many directories were removed.
+    pub fn remove_stale(game_id: &str) -> usize {
+        remove_stale_in(Path::new("/tmp"), Some(game_id))
+    }
+    /// Removes what attempts at any game left behind when their driver died
+    pub fn reap_stale() -> usize {
+        remove_stale_in(Path::new("/tmp"), None)
     }
     pub fn get_path(&self) -> &str {
         &self.full_path
@@ -19,11 +33,30 @@ impl Drop for GameDir {
     }
 }
 
+fn remove_stale_in(root: &Path, game_id: Option<&str>) -> usize {
+    let Ok(entries) = std::fs::read_dir(root) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .filter(|entry| {
+            let name = entry.file_name();
+            match name.to_str().and_then(parse_attempt) {
+                Some((id, dead)) => dead && game_id.is_none_or(|game_id| game_id == id),
+                None => false,
+            }
+        })
+        .filter(|entry| std::fs::remove_dir_all(entry.path()).is_ok())
+        .count()
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{io::Write, path::Path};
+    use std::{fs, io::Write, path::Path};
 
-    use super::GameDir;
+    use super::{remove_stale_in, GameDir};
+    use crate::{sandbox::lifecycle::local_instance, utils::TestDir};
 
     #[test]
     fn dir_creation_and_deletion_check() {
@@ -43,4 +76,36 @@ mod tests {
 
         assert!(!Path::new(&full_path).exists());
     }
+
+    #[test]
+    fn every_attempt_gets_its_own_dir() {
+        let game_id = "5c0cbd4e-34a1-44a6-9ad6-2b1c9f0d6a8e";
+        let first = GameDir::new(game_id).unwrap();
+        let second = GameDir::new(game_id).unwrap();
+        assert_ne!(first.get_path(), second.get_path());
+        assert!(Path::new(first.get_path()).exists());
+        assert!(Path::new(second.get_path()).exists());
+    }
+
+    #[test]
+    fn only_dirs_of_dead_instances_are_stale() {
+        let root = TestDir::new("game_dir_only_dirs_of_dead_instances_are_stale");
+        let (pid, start) = local_instance();
+        let game_id = "5c0cbd4e-34a1-44a6-9ad6-2b1c9f0d6a8e";
+        let live = format!("{game_id}-{pid}.{start}-0");
+        let dead = format!("{game_id}-{pid}.{}-0", start + 1);
+        let other_game = format!("1-{pid}.{}-0", start + 1);
+        for name in [&live, &dead, &other_game, &"some-other.dir-0".to_owned()] {
+            fs::create_dir(root.join(name)).unwrap();
+        }
+
+        assert_eq!(remove_stale_in(&root, Some(game_id)), 1);
+        assert!(root.join(&live).exists());
+        assert!(!root.join(&dead).exists());
+        assert!(root.join(&other_game).exists());
+
+        assert_eq!(remove_stale_in(&root, None), 1);
+        assert!(!root.join(&other_game).exists());
+        assert!(root.join("some-other.dir-0").exists());
+    }
 }
@@ -3,15 +3,24 @@ use std::collections::HashMap;
 
 use error::SimulatorError;
 use log::error;
-use response::{GameResult, GameStatusEnum};
+use response::{GameProgress, GameResult, GameStatusEnum, ParticipantResult};
+pub mod cache;
+pub mod compression;
+pub mod config;
+pub mod diagnostics;
 pub mod error;
 pub mod fifo;
 pub mod game_dir;
+pub mod metrics;
 pub mod mq;
 pub mod poll;
+pub mod progress;
+pub mod replay;
 pub mod request;
 pub mod response;
 pub mod runner;
+pub mod sandbox;
+pub mod shutdown;
 pub mod utils;
 
 fn get_turnwise_logs(player_log: String) -> HashMap<usize, Vec<String>> {
@@ -49,12 +58,30 @@ fn get_turnwise_logs(player_log: String) -> HashMap<usize, Vec<String>> {
     turnwise_logs
 }
 
+/// What one participant left behind after the game
+pub struct ParticipantOutput {
+    pub tag: String,
+    pub log: String,
+    pub compile_info: runner::CompileInfo,
+}
+
 pub fn create_final_response(
     game_request: request::GameRequest,
-    player_log: String,
+    participants: Vec<ParticipantOutput>,
     simulator_log: String,
 ) -> response::GameStatus {
-    let turnwise_logs = get_turnwise_logs(player_log);
+    let pvp = participants.len() > 1;
+    let turnwise_logs = participants
+        .iter()
+        .map(|p| get_turnwise_logs(p.log.clone()))
+        .collect::<Vec<_>>();
+
+    let prints = participants
+        .iter()
+        .zip(&turnwise_logs)
+        .map(|(p, logs)| (pvp.then_some(p.tag.as_str()), logs))
+        .collect::<Vec<_>>();
+    let replay = replay::ReplayParser::default().parse(&simulator_log, &prints);
 
     let mut final_logs = String::new();
 
@@ -71,9 +98,13 @@ pub fn create_final_response(
                 .strip_prefix("TURN, ")
                 .and_then(|x| x.parse::<usize>().ok())
             {
-                if turnwise_logs.contains_key(&num) {
-                    for log in turnwise_logs.get(&num).unwrap().iter() {
-                        final_logs.push_str(&format!("PRINT, {log}\n"));
+                for (participant, logs) in participants.iter().zip(&turnwise_logs) {
+                    for log in logs.get(&num).into_iter().flatten() {
+                        if pvp {
+                            final_logs.push_str(&format!("PRINT, {}, {log}\n", participant.tag));
+                        } else {
+                            final_logs.push_str(&format!("PRINT, {log}\n"));
+                        }
                     }
                 }
             }
@@ -102,6 +133,23 @@ pub fn create_final_response(
         }
     }
 
+    let (compile_cache, participants) = if pvp {
+        let results = participants
+            .into_iter()
+            .map(|p| ParticipantResult {
+                tag: p.tag,
+                has_errors: false,
+                compile_cache: p.compile_info.cache,
+            })
+            .collect();
+        (None, results)
+    } else {
+        (
+            participants.first().and_then(|p| p.compile_info.cache),
+            vec![],
+        )
+    };
+
     response::GameStatus {
         game_id: game_request.game_id,
         game_status: GameStatusEnum::EXECUTED,
@@ -110,7 +158,14 @@ pub fn create_final_response(
             coins_used: (game_request.parameters.no_of_coins - coins_left) as u64,
             has_errors: false,
             log: final_logs,
+            compile_cache,
+            participants,
+            diagnostics: vec![],
+            replay: Some(replay),
+            log_encoding: None,
+            metrics: vec![],
         }),
+        progress: None,
     }
 }
 
@@ -119,6 +174,16 @@ pub fn create_executing_response(game_request: &request::GameRequest) -> respons
         game_id: game_request.game_id.to_string(),
         game_status: GameStatusEnum::EXECUTING,
         game_result: None,
+        progress: None,
+    }
+}
+
+pub fn create_progress_response(game_id: &str, progress: GameProgress) -> response::GameStatus {
+    response::GameStatus {
+        game_id: game_id.to_owned(),
+        game_status: GameStatusEnum::PROGRESS,
+        game_result: None,
+        progress: Some(progress),
     }
 }
 
@@ -127,15 +192,22 @@ pub fn create_error_response(
     err: SimulatorError,
 ) -> response::GameStatus {
     error!("Error in execution: {:?}", err);
+    let mut diagnostics = vec![];
     let (err_type, error) = match err {
         SimulatorError::RuntimeError(e) => ("Runtime Error!".to_owned(), e),
-        SimulatorError::CompilationError(e) => ("Compilation Error!".to_owned(), e),
+        SimulatorError::CompilationError(e) => {
+            diagnostics = diagnostics::parse(&e);
+            ("Compilation Error!".to_owned(), e)
+        }
         SimulatorError::FifoCreationError(e) => ("Process Communication Error!".to_owned(), e),
         SimulatorError::UnidentifiedError(e) => {
             ("Unidentified Error. Contact the POCs!".to_owned(), e)
         }
         SimulatorError::TimeOutError(e) => ("Timeout Error!".to_owned(), e),
+        SimulatorError::MemoryLimitExceeded(e) => ("Memory Limit Exceeded!".to_owned(), e),
+        SimulatorError::CpuTimeLimitExceeded(e) => ("CPU Time Limit Exceeded!".to_owned(), e),
         SimulatorError::EpollError(e) => ("Event Creation Error!".to_owned(), e),
+        SimulatorError::ShutdownError(e) => ("Driver Shutdown!".to_owned(), e),
     };
 
     let error = error
@@ -152,8 +224,37 @@ pub fn create_error_response(
             coins_used: 0,
             has_errors: true,
             log: format!("ERRORS, ERROR TYPE: {err_type}\nERRORS, ERROR LOG:\n{error}\n"),
+            compile_cache: None,
+            participants: vec![],
+            diagnostics,
+            replay: None,
+            log_encoding: None,
+            metrics: vec![],
         }),
+        progress: None,
+    }
+}
+
+/// Like `create_error_response`, but blames one participant of a player-vs-player match
+pub fn create_participant_error_response(
+    game_request: &request::GameRequest,
+    tag: &str,
+    err: SimulatorError,
+) -> response::GameStatus {
+    let mut response = create_error_response(game_request, err);
+    if let (true, Some(result)) = (game_request.is_pvp(), &mut response.game_result) {
+        result.log = format!("ERRORS, PARTICIPANT: {tag}\n{}", result.log);
+        result.participants = game_request
+            .participants()
+            .iter()
+            .map(|p| ParticipantResult {
+                tag: p.tag.to_owned(),
+                has_errors: p.tag == tag,
+                compile_cache: None,
+            })
+            .collect();
     }
+    response
 }
 
 #[cfg(test)]
@@ -161,8 +262,13 @@ mod tests {
 
     use crate::{
         create_final_response, get_turnwise_logs,
-        request::{GameParameters, GameRequest, Language},
-        response::{GameResult, GameStatus, GameStatusEnum},
+        request::{tests::example_request, GameRequest, Language, PlayerCode},
+        response::{
+            CompileCacheStatus, GameResult, GameStatus, GameStatusEnum, ParticipantResult,
+            PlayerPrint, Replay, ReplayTurn,
+        },
+        runner::CompileInfo,
+        ParticipantOutput,
     };
 
     #[test]
@@ -220,23 +326,18 @@ mod tests {
             TURN, 100
             DESTRUCTION, 75.0%
             COINS, 10"#;
-        let dummy_game_request = GameRequest {
-            game_id: "1".to_owned(),
-            parameters: GameParameters {
-                attackers: vec![],
-                defenders: vec![],
-                no_of_turns: 500,
-                no_of_coins: 500,
-            },
-            language: Language::CPP,
-            source_code: "".to_owned(),
-            map: vec![vec![]],
-        };
+        let dummy_game_request = example_request();
 
         let tot_coins = dummy_game_request.parameters.no_of_coins;
         let result = create_final_response(
             dummy_game_request,
-            player_logs.to_owned(),
+            vec![ParticipantOutput {
+                tag: "player".to_owned(),
+                log: player_logs.to_owned(),
+                compile_info: CompileInfo {
+                    cache: Some(CompileCacheStatus::HIT),
+                },
+            }],
             simulator_logs.to_owned(),
         );
 
@@ -247,10 +348,118 @@ mod tests {
                 destruction_percentage: 75.0,
                 coins_used: (tot_coins - 10) as u64,
                 has_errors: false,
-                log: "TURN, 1\nPRINT, Bug is here\nPRINT, No it's here\nCOINS, 100\nDESTRUCTION, 20.0%\nTURN, 3\nCOINS, 100\nDESTRUCTION, 20.0%\nTURN, 100\nPRINT, Nope, it's been here the whole time\nDESTRUCTION, 75.0%\nCOINS, 10\n".to_owned()
+                log: "TURN, 1\nPRINT, Bug is here\nPRINT, No it's here\nCOINS, 100\nDESTRUCTION, 20.0%\nTURN, 3\nCOINS, 100\nDESTRUCTION, 20.0%\nTURN, 100\nPRINT, Nope, it's been here the whole time\nDESTRUCTION, 75.0%\nCOINS, 10\n".to_owned(),
+                compile_cache: Some(CompileCacheStatus::HIT),
+                participants: vec![],
+                diagnostics: vec![],
+                replay: Some(Replay {
+                    events: vec![],
+                    turns: vec![
+                        ReplayTurn {
+                            turn: 1,
+                            coins: Some(100),
+                            destruction: Some(20.0),
+                            prints: vec![
+                                PlayerPrint {
+                                    tag: None,
+                                    message: "Bug is here".to_owned(),
+                                },
+                                PlayerPrint {
+                                    tag: None,
+                                    message: "No it's here".to_owned(),
+                                },
+                            ],
+                            events: vec![],
+                        },
+                        ReplayTurn {
+                            turn: 3,
+                            coins: Some(100),
+                            destruction: Some(20.0),
+                            prints: vec![],
+                            events: vec![],
+                        },
+                        ReplayTurn {
+                            turn: 100,
+                            coins: Some(10),
+                            destruction: Some(75.0),
+                            prints: vec![PlayerPrint {
+                                tag: None,
+                                message: "Nope, it's been here the whole time".to_owned(),
+                            }],
+                            events: vec![],
+                        },
+                    ],
+                }),
+                log_encoding: None,
+                metrics: vec![],
             }),
+            progress: None,
         };
 
         assert_eq!(expected_game_status, result);
     }
+
+    #[test]
+    fn pvp_final_response_test() {
+        let attacker_logs = "TURN 1\nspawning\nENDLOG\n";
+        let defender_logs = "TURN 1\nholding\nENDLOG\nTURN 2\nstill holding\nENDLOG\n";
+        let simulator_logs = "TURN, 1\nTURN, 2\nDESTRUCTION, 10.0%\nCOINS, 400";
+        let dummy_game_request = GameRequest {
+            defender: Some(PlayerCode {
+                source_code: "".to_owned(),
+                language: Language::from("PYTHON"),
+            }),
+            ..example_request()
+        };
+
+        let result = create_final_response(
+            dummy_game_request,
+            vec![
+                ParticipantOutput {
+                    tag: "attacker".to_owned(),
+                    log: attacker_logs.to_owned(),
+                    compile_info: CompileInfo {
+                        cache: Some(CompileCacheStatus::MISS),
+                    },
+                },
+                ParticipantOutput {
+                    tag: "defender".to_owned(),
+                    log: defender_logs.to_owned(),
+                    compile_info: CompileInfo::default(),
+                },
+            ],
+            simulator_logs.to_owned(),
+        );
+
+        let game_result = result.game_result.unwrap();
+        assert_eq!(
+            game_result.log,
+            "TURN, 1\nPRINT, attacker, spawning\nPRINT, defender, holding\nTURN, 2\nPRINT, defender, still holding\nDESTRUCTION, 10.0%\nCOINS, 400\n"
+        );
+        assert_eq!(game_result.coins_used, 100);
+        let turns = game_result.replay.unwrap().turns;
+        assert_eq!(
+            turns[1].prints,
+            vec![PlayerPrint {
+                tag: Some("defender".to_owned()),
+                message: "still holding".to_owned(),
+            }]
+        );
+        assert_eq!(game_result.compile_cache, None);
+        assert_eq!(
+            game_result.participants,
+            vec![
+                ParticipantResult {
+                    tag: "attacker".to_owned(),
+                    has_errors: false,
+                    compile_cache: Some(CompileCacheStatus::MISS),
+                },
+                ParticipantResult {
+                    tag: "defender".to_owned(),
+                    has_errors: false,
+                    compile_cache: None,
+                },
+            ]
+        );
+    }
 }
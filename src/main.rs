@@ -1,20 +1,35 @@
-use std::{env, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::Path,
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use cc_driver::{
-    create_error_response, create_executing_response,
+    compression,
+    config::DriverConfig,
+    create_error_response, create_executing_response, create_participant_error_response,
     error::SimulatorError,
     fifo::Fifo,
     game_dir::GameDir,
-    mq::{consumer, Publisher},
+    metrics::{GameMetrics, Usage},
+    mq::{consumer, Publisher, Receipt},
     poll::{
+        capture::LogCapture,
         epoll::{CallbackMessage, EpollGeneric},
-        epoll_entry::{EpollEntryType, Process, ProcessOutput, ProcessType},
+        epoll_entry::{Deadline, EpollEntryType, Process, ProcessOutput, ProcessType},
     },
-    request::{GameRequest, Language},
-    response::GameStatus,
-    runner::{cpp, java, py, simulator, Runnable},
+    progress::{ProgressReporter, SimulatorProgress},
+    request::GameRequest,
+    response::{GamePhase, GameStatus, GameStatusEnum},
+    runner::{exit_error, player, simulator, Runnable},
+    sandbox::{self, pool::WarmPool, SandboxBackend},
+    shutdown::Shutdown,
+    ParticipantOutput,
 };
-use log::{info, LevelFilter};
+use log::{error, info, warn, LevelFilter};
 use log4rs::{
     append::{
         console::{ConsoleAppender, Target},
@@ -25,61 +40,91 @@ use log4rs::{
 };
 use nix::sys::epoll::EpollFlags;
 
+/// An error together with the tag of the participant that caused it, if any
+type Failure = (Option<String>, SimulatorError);
+
+/// A process that exited, with what it used
+type Exit = (ProcessType, ExitStatus, Usage);
+
+/// Kills every process still registered with `epoll_handle`
+fn kill_all(epoll_handle: &mut EpollGeneric<EpollEntryType>) {
+    let killable_processes = epoll_handle
+        .get_registered_fds()
+        .iter()
+        .filter_map(|x| match x.1 {
+            EpollEntryType::Process(_) => Some(*x.0),
+            _ => None,
+        })
+        .collect::<Vec<u64>>();
+    killable_processes.iter().for_each(|x| {
+        if let EpollEntryType::Process(mut p) = epoll_handle.unregister(*x).unwrap() {
+            p.kill()
+        }
+    });
+}
+
+/// Outputs of the processes that closed their stderr. Every process that exited is added to
+/// `exits`. A failing one kills all other processes and is the last exit added, the caller
+/// tells why it failed. Reaching the deadline or the driver shutting down kills all processes
+/// and fails the game.
 fn handle_event(
     epoll_handle: &mut EpollGeneric<EpollEntryType>,
-) -> Result<Vec<Option<ProcessOutput>>, SimulatorError> {
-    let events = epoll_handle.poll(
-        env::var("EPOLL_WAIT_TIMEOUT").unwrap().parse().unwrap(),
-        epoll_handle.get_registered_fds().len(),
-    )?;
+    epoll_wait_timeout: isize,
+    exits: &mut Vec<Exit>,
+) -> Result<Vec<Option<ProcessOutput>>, Failure> {
+    let events = epoll_handle
+        .poll(epoll_wait_timeout, epoll_handle.get_registered_fds().len())
+        .map_err(|e| (None, SimulatorError::from(e)))?;
     let mut res = vec![];
     for e in events {
-        match epoll_handle.process_event(e)? {
+        match epoll_handle.process_event(e).map_err(|e| (None, e))? {
             CallbackMessage::Unregister(fd) => {
                 // Means it's a stderr handle
-                let entry = epoll_handle.unregister(fd as u64)?;
+                let entry = epoll_handle
+                    .unregister(fd as u64)
+                    .map_err(|e| (None, SimulatorError::from(e)))?;
                 res.push(match entry {
-                    EpollEntryType::Process(_) => unreachable!(),
                     EpollEntryType::StdErr(output) => Some(output),
+                    EpollEntryType::Process(_)
+                    | EpollEntryType::Deadline(_)
+                    | EpollEntryType::Shutdown(_) => unreachable!(),
                 });
             }
             CallbackMessage::HandleExplicitly(fd) => {
-                // Means its a process handle
-                let entry = epoll_handle.unregister(fd as u64)?;
+                // Means its a process handle or the deadline
+                let entry = epoll_handle
+                    .unregister(fd as u64)
+                    .map_err(|e| (None, SimulatorError::from(e)))?;
                 match entry {
                     EpollEntryType::StdErr(_) => unreachable!(),
+                    EpollEntryType::Deadline(deadline) => {
+                        kill_all(epoll_handle);
+                        return Err((
+                            None,
+                            SimulatorError::TimeOutError(format!(
+                                "Game did not finish within {} seconds, so it was stopped",
+                                deadline.limit().as_secs()
+                            )),
+                        ));
+                    }
+                    EpollEntryType::Shutdown(_) => {
+                        kill_all(epoll_handle);
+                        return Err((
+                            None,
+                            SimulatorError::ShutdownError(
+                                "The driver shut down before the game finished".to_owned(),
+                            ),
+                        ));
+                    }
                     EpollEntryType::Process(mut p) => {
-                        let exit_status = p.wait()?;
+                        let (exit_status, usage) = p.wait().map_err(|e| (None, e))?;
+                        exits.push((p.get_type().clone(), exit_status, usage));
 
                         if exit_status.success() {
                             res.push(None);
                         } else {
-                            let killable_processes = epoll_handle
-                                .get_registered_fds()
-                                .iter()
-                                .filter_map(|x| match x.1 {
-                                    EpollEntryType::Process(_) => Some(*x.0),
-                                    _ => None,
-                                })
-                                .collect::<Vec<u64>>();
-                            killable_processes.iter().for_each(|x| {
-                                match epoll_handle.unregister(*x).unwrap() {
-                                    EpollEntryType::Process(mut p) => p.kill(),
-                                    EpollEntryType::StdErr(_) => unreachable!(),
-                                }
-                            });
-
-                            return Err(match exit_status.code() {
-                            // 137 => Stands for container killing itself (by SIGKILL)
-                            // that will be due to contraint provided
-                            None | Some(137) => SimulatorError::TimeOutError(
-                                "Process took longer than the specified time to execute, so it was killed"
-                                    .to_owned(),
-                            ),
-                            Some(code) => SimulatorError::RuntimeError(format!(
-                                "Program exited with non zero exit code: {code}"
-                            )),
-                            });
+                            kill_all(epoll_handle);
+                            return Ok(res);
                         }
                     }
                 }
@@ -92,11 +137,117 @@ fn handle_event(
     Ok(res)
 }
 
-fn handler(game_request: GameRequest) -> GameStatus {
+/// FIFO ends handed to the processes of a game
+struct Topology {
+    /// stdin and stdout of every participant, in participant order
+    players: Vec<(File, File)>,
+    simulator: (File, File),
+    /// Ends the simulator opens by path, kept open by the driver until it exits
+    held: Vec<File>,
+}
+
+/// A single player talks to the simulator over its stdin and stdout. In a
+/// player-vs-player match the simulator reads the participant tags and the game from
+/// stdin and finds `{tag}_in` / `{tag}_out` for every participant in the FIFO directory.
+fn connect(
+    fifo_dir: &str,
+    tags: &[String],
+    game_request: &GameRequest,
+    map_size: u32,
+    fifos: &mut Vec<Fifo>,
+) -> Result<Topology, SimulatorError> {
+    let mut open = |name: String| -> Result<(File, File), SimulatorError> {
+        let mut fifo = Fifo::new(format!("{fifo_dir}/{name}"))?;
+        let ends = fifo.get_ends().unwrap();
+        fifos.push(fifo);
+        Ok(ends)
+    };
+
+    if let [tag] = tags {
+        let (player_stdin, sim_stdout) = open(format!("{tag}_in"))?;
+        let (sim_stdin, player_stdout) = open(format!("{tag}_out"))?;
+        cc_driver::utils::send_initial_input(
+            vec![&player_stdout, &sim_stdout],
+            game_request,
+            map_size,
+        );
+        return Ok(Topology {
+            players: vec![(player_stdin, player_stdout)],
+            simulator: (sim_stdin, sim_stdout),
+            held: vec![],
+        });
+    }
+
+    let (sim_stdin, control) = open("simulator_in".to_owned())?;
+    cc_driver::utils::send_participants(&control, tags);
+    cc_driver::utils::send_initial_input(vec![&control], game_request, map_size);
+    drop(control);
+    let sim_stdout = File::options()
+        .write(true)
+        .open("/dev/null")
+        .map_err(|e| SimulatorError::FifoCreationError(format!("{e}")))?;
+
+    let mut players = vec![];
+    let mut held = vec![];
+    for tag in tags {
+        let (player_stdin, to_player) = open(format!("{tag}_in"))?;
+        let (from_player, player_stdout) = open(format!("{tag}_out"))?;
+        cc_driver::utils::send_initial_input(vec![&to_player], game_request, map_size);
+        players.push((player_stdin, player_stdout));
+        held.extend([to_player, from_player]);
+    }
+    Ok(Topology {
+        players,
+        simulator: (sim_stdin, sim_stdout),
+        held,
+    })
+}
+
+fn register(
+    event_handler: &mut EpollGeneric<EpollEntryType>,
+    mut child: std::process::Child,
+    process_type: ProcessType,
+    held: Vec<File>,
+    capture: LogCapture,
+    observer: Option<Arc<Mutex<SimulatorProgress>>>,
+) -> Result<(), SimulatorError> {
+    let stderr = child.stderr.take().unwrap();
+    let process = Process::holding(child, process_type.clone(), held);
+    let mut output = ProcessOutput::new(stderr, process_type, capture);
+    if let Some(observer) = observer {
+        output = output.observed_by(observer);
+    }
+
+    event_handler
+        .register(
+            EpollEntryType::Process(process),
+            EpollFlags::EPOLLIN | EpollFlags::EPOLLHUP,
+        )
+        .map_err(SimulatorError::from)?;
+    event_handler
+        .register(
+            EpollEntryType::StdErr(output),
+            EpollFlags::EPOLLIN | EpollFlags::EPOLLHUP,
+        )
+        .map_err(SimulatorError::from)?;
+    Ok(())
+}
+
+/// Runs one game, handing intermediate `PROGRESS` statuses to `publish`. The game gives up
+/// once `shutdown` aborts it.
+fn handler(
+    game_request: GameRequest,
+    config: &Arc<DriverConfig>,
+    sandbox: &Arc<dyn SandboxBackend>,
+    publish: &dyn Fn(GameStatus),
+    shutdown: &Arc<Shutdown>,
+) -> GameStatus {
     info!(
-        "Starting execution for {} with language {:?}",
+        "Starting execution for {} with language {}",
         game_request.game_id, game_request.language
     );
+    let mut reporter = ProgressReporter::new(&game_request, config.progress_interval, publish);
+    let metrics = GameMetrics::default();
     let game_dir_handle = GameDir::new(&game_request.game_id);
 
     if game_dir_handle.is_none() {
@@ -109,136 +260,372 @@ fn handler(game_request: GameRequest) -> GameStatus {
     }
 
     let game_dir_handle = game_dir_handle.unwrap();
+    let game_dir = game_dir_handle.get_path();
+    let pvp = game_request.is_pvp();
+
+    let mut runners: Vec<(String, Box<dyn Runnable>)> = vec![];
+    for participant in game_request.participants() {
+        // Every participant gets its own directory so that artifacts are never shared
+        let dir = format!("{game_dir}/{}", participant.tag);
+        let language = match config.language(participant.language.name()) {
+            Some(language) => language.clone(),
+            None => {
+                return create_participant_error_response(
+                    &game_request,
+                    participant.tag,
+                    SimulatorError::UnidentifiedError(format!(
+                        "Unsupported language {}",
+                        participant.language
+                    )),
+                )
+            }
+        };
+        let player_code_file = format!("{dir}/{}", language.source_file);
+
+        if let Err(e) = std::fs::create_dir(&dir) {
+            return create_error_response(
+                &game_request,
+                SimulatorError::UnidentifiedError(format!(
+                    "Failed to create directory for {}: {e}",
+                    participant.tag
+                )),
+            );
+        }
+        if let Some(resp) = cc_driver::utils::make_copy(
+            &language.boilerplate_dir,
+            &dir,
+            &player_code_file,
+            participant.source_code,
+            &game_request,
+        ) {
+            return resp;
+        }
 
-    let (to_copy_dir, player_code_file) = match game_request.language {
-        cc_driver::request::Language::CPP => (
-            "player_code/cpp",
-            format!("{}/run.cpp", game_dir_handle.get_path()),
-        ),
-        cc_driver::request::Language::PYTHON => (
-            "player_code/python",
-            format!("{}/run.py", game_dir_handle.get_path()),
-        ),
-        cc_driver::request::Language::JAVA => (
-            "player_code/java",
-            format!("{}/Run.java", game_dir_handle.get_path()),
-        ),
-    };
-
-    if let Some(resp) = cc_driver::utils::make_copy(
-        to_copy_dir,
-        game_dir_handle.get_path(),
-        &player_code_file,
-        &game_request,
-    ) {
-        return resp;
+        let id = if pvp {
+            format!("{}_{}", game_request.game_id, participant.tag)
+        } else {
+            game_request.game_id.to_string()
+        };
+        let runner: Box<dyn Runnable> = Box::new(player::Runner::new(
+            dir,
+            id,
+            pvp.then(|| participant.tag.to_owned()),
+            language,
+            Arc::clone(config),
+            Arc::clone(sandbox),
+        ));
+        runners.push((participant.tag.to_owned(), runner));
     }
+    let tags = runners
+        .iter()
+        .map(|(tag, _)| tag.clone())
+        .collect::<Vec<_>>();
+
+    let fifo_dir = format!("{game_dir}/fifos");
+    let mut fifos = vec![];
+    let topology = match std::fs::create_dir(&fifo_dir)
+        .map_err(|e| SimulatorError::FifoCreationError(format!("{e}")))
+        .and_then(|_| connect(&fifo_dir, &tags, &game_request, config.map_size, &mut fifos))
+    {
+        Ok(topology) => topology,
+        Err(e) => return create_error_response(&game_request, e),
+    };
 
-    let p1_in = format!("{}/p1_in", game_dir_handle.get_path());
-    let p2_in = format!("{}/p2_in", game_dir_handle.get_path());
+    let simulator_progress = reporter.simulator();
+    let initialize = || -> Result<_, Failure> {
+        let mut compile_infos = vec![];
+        let compile_deadline = Instant::now() + Duration::from_secs(config.compile_deadline);
+        for (tag, runner) in &runners {
+            compile_infos.push(
+                runner
+                    .compile(&metrics, compile_deadline, shutdown)
+                    .map_err(|e| (Some(tag.clone()), e))?,
+            );
+        }
 
-    let pipe1 = Fifo::new(p1_in);
-    let pipe2 = Fifo::new(p2_in);
+        let mut event_handler =
+            EpollGeneric::<EpollEntryType>::new().map_err(|e| (None, SimulatorError::from(e)))?;
+
+        for ((tag, runner), (stdin, stdout)) in runners.iter().zip(topology.players) {
+            let process = runner
+                .run(stdin, stdout)
+                .map_err(|e| (Some(tag.clone()), e))?;
+            let process_type = ProcessType::Runner(tag.clone());
+            let capture = LogCapture::for_process(config, &process_type, Path::new(game_dir));
+            register(
+                &mut event_handler,
+                process,
+                process_type,
+                vec![],
+                capture,
+                None,
+            )
+            .map_err(|e| (None, e))?;
+        }
 
-    match (pipe1, pipe2) {
-        (Ok(mut p1), Ok(mut p2)) => {
-            let (p1_stdin, p2_stdout) = p1.get_ends().unwrap();
-            let (p2_stdin, p1_stdout) = p2.get_ends().unwrap();
+        let simulator = if pvp {
+            simulator::Simulator::new_pvp(
+                game_request.game_id.to_string(),
+                fifo_dir.clone(),
+                Arc::clone(config),
+                Arc::clone(sandbox),
+            )
+        } else {
+            simulator::Simulator::new(
+                game_request.game_id.to_string(),
+                Arc::clone(config),
+                Arc::clone(sandbox),
+            )
+        };
+        let (sim_stdin, sim_stdout) = topology.simulator;
+        let sim_process = simulator
+            .run(sim_stdin, sim_stdout)
+            .map_err(|e| (None, e))?;
+        register(
+            &mut event_handler,
+            sim_process,
+            ProcessType::Simulator,
+            topology.held,
+            LogCapture::for_process(config, &ProcessType::Simulator, Path::new(game_dir)),
+            Some(Arc::clone(&simulator_progress)),
+        )
+        .map_err(|e| (None, e))?;
+
+        // The shortest wall time limit of the run phase, processes blocked on I/O never hit
+        // their CPU time limit
+        let run_deadline = runners
+            .iter()
+            .map(|(_, runner)| runner.wall_time_limit())
+            .chain([simulator.wall_time_limit()])
+            .flatten()
+            .fold(config.run_deadline, u64::min);
+        let deadline = Deadline::after(Duration::from_secs(run_deadline)).map_err(|e| (None, e))?;
+        event_handler
+            .register(EpollEntryType::Deadline(deadline), EpollFlags::EPOLLIN)
+            .map_err(|e| (None, SimulatorError::from(e)))?;
+        event_handler
+            .register(
+                EpollEntryType::Shutdown(Arc::clone(shutdown)),
+                EpollFlags::EPOLLIN,
+            )
+            .map_err(|e| (None, SimulatorError::from(e)))?;
+
+        Ok((event_handler, compile_infos, simulator))
+    };
 
-            cc_driver::utils::send_initial_input(vec![&p1_stdout, &p2_stdout], &game_request);
+    let fail = |(tag, err): Failure| {
+        // Stopped right away instead of once the backend is dropped after publishing
+        sandbox.cleanup();
+        let mut response = match tag {
+            Some(tag) => create_participant_error_response(&game_request, &tag, err),
+            None => create_error_response(&game_request, err),
+        };
+        metrics.attach(&mut response);
+        response
+    };
 
-            let runner: Box<dyn Runnable> = match game_request.language {
-                Language::CPP => Box::new(cpp::Runner::new(
-                    game_dir_handle.get_path().to_string(),
-                    game_request.game_id.to_string(),
-                )),
-                Language::PYTHON => Box::new(py::Runner::new(
-                    game_dir_handle.get_path().to_string(),
-                    game_request.game_id.to_string(),
-                )),
-                Language::JAVA => Box::new(java::Runner::new(
-                    game_dir_handle.get_path().to_string(),
-                    game_request.game_id.to_string(),
-                )),
-            };
+    reporter.phase(GamePhase::COMPILING);
+    let (mut event_handler, compile_infos, simulator) = match initialize() {
+        Ok(initialized) => initialized,
+        Err(failure) => return fail(failure),
+    };
+    reporter.phase(GamePhase::RUNNING);
 
-            let initialize = || -> Result<_, SimulatorError> {
-                let mut player_process = runner.run(p1_stdin, p1_stdout)?;
-                let simulator = simulator::Simulator::new(game_request.game_id.to_string());
-                let mut sim_process = simulator.run(p2_stdin, p2_stdout)?;
-
-                let player_stderr = player_process.stderr.take().unwrap();
-                let sim_stderr = sim_process.stderr.take().unwrap();
-
-                let player_process = Process::new(player_process, ProcessType::Runner);
-                let sim_process = Process::new(sim_process, ProcessType::Simulator);
-                let player_output = ProcessOutput::new(player_stderr, ProcessType::Runner);
-                let sim_output = ProcessOutput::new(sim_stderr, ProcessType::Simulator);
-
-                let player = EpollEntryType::Process(player_process);
-                let player_output = EpollEntryType::StdErr(player_output);
-                let sim = EpollEntryType::Process(sim_process);
-                let sim_output = EpollEntryType::StdErr(sim_output);
-
-                let mut event_handler =
-                    EpollGeneric::<EpollEntryType>::new().map_err(SimulatorError::from)?;
-
-                event_handler
-                    .register(player, EpollFlags::EPOLLIN | EpollFlags::EPOLLHUP)
-                    .map_err(SimulatorError::from)?;
-                event_handler
-                    .register(player_output, EpollFlags::EPOLLIN | EpollFlags::EPOLLHUP)
-                    .map_err(SimulatorError::from)?;
-                event_handler
-                    .register(sim, EpollFlags::EPOLLIN | EpollFlags::EPOLLHUP)
-                    .map_err(SimulatorError::from)?;
-                event_handler
-                    .register(sim_output, EpollFlags::EPOLLIN | EpollFlags::EPOLLHUP)
-                    .map_err(SimulatorError::from)?;
-
-                Ok(event_handler)
-            };
+    let mut outputs: Vec<ProcessOutput> = vec![];
 
-            let mut event_handler = match initialize() {
-                Ok(handler) => handler,
-                Err(err) => return create_error_response(&game_request, err),
+    // The deadline and shutdown stay registered until the game is over
+    while event_handler.get_registered_fds().values().any(|entry| {
+        matches!(
+            entry,
+            EpollEntryType::Process(_) | EpollEntryType::StdErr(_)
+        )
+    }) {
+        let mut exits = vec![];
+        let result = handle_event(&mut event_handler, config.epoll_wait_timeout, &mut exits);
+        for (process_type, status, usage) in exits {
+            let (tag, process): (_, &dyn Runnable) = match &process_type {
+                ProcessType::Runner(tag) => match runners.iter().find(|(t, _)| t == tag) {
+                    Some((_, runner)) => (Some(tag.clone()), runner.as_ref()),
+                    None => continue,
+                },
+                ProcessType::Simulator => (None, &simulator),
             };
+            let process_metrics = process.measure(usage);
+            let error = (!status.success()).then(|| {
+                exit_error(
+                    status,
+                    process.exit_signal(status),
+                    &process_metrics,
+                    process.out_of_memory(),
+                )
+            });
+            metrics.record(process_metrics);
+            if let Some(error) = error {
+                return fail((tag, error));
+            }
+        }
+        match result {
+            Ok(processing_outputs) => outputs.extend(processing_outputs.into_iter().flatten()),
+            Err(failure) => return fail(failure),
+        }
+        reporter.tick();
+    }
 
-            let mut outputs: Vec<ProcessOutput> = vec![];
-
-            while !event_handler.is_empty() {
-                let result = handle_event(&mut event_handler);
-                match result {
-                    Ok(processing_outputs) => {
-                        outputs.extend(processing_outputs.into_iter().flatten())
-                    }
-                    Err(err) => return create_error_response(&game_request, err),
-                }
+    let mut simulator_log = String::new();
+    let mut player_logs = HashMap::new();
+    for output in outputs {
+        let process_type = output.process_type().clone();
+        let log = match output.output() {
+            Ok(log) => log,
+            Err(e) => return fail((None, e)),
+        };
+        match process_type {
+            ProcessType::Runner(tag) => {
+                player_logs.insert(tag, log);
             }
+            ProcessType::Simulator => simulator_log = log,
+        }
+    }
+    let participants = tags
+        .into_iter()
+        .zip(compile_infos)
+        .map(|(tag, compile_info)| ParticipantOutput {
+            log: player_logs.remove(&tag).unwrap_or_default(),
+            tag,
+            compile_info,
+        })
+        .collect();
+
+    info!("Successfully executed for game {}", game_request.game_id);
+    let mut response = cc_driver::create_final_response(game_request, participants, simulator_log);
+    metrics.attach(&mut response);
+    response
+}
 
-            let process1 = outputs.remove(0);
-            let process2 = outputs.remove(0);
+/// First wait of a worker after a status could not be published, doubled for every further
+/// failure in a row up to 32 seconds
+const PUBLISH_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Runs the games handed over by the consumer. A game's delivery is acked once its final status
+/// was published. When a status cannot be published the game is requeued and the worker waits
+/// before taking the next one, longer after every failure in a row, while the publisher
+/// reconnects.
+fn worker_fn(
+    msg_receiver: crossbeam_channel::Receiver<(GameRequest, Receipt)>,
+    publisher: Arc<Publisher>,
+    config: Arc<DriverConfig>,
+    pool: Option<Arc<WarmPool>>,
+    shutdown: Arc<Shutdown>,
+) {
+    let mut failures = 0;
+    loop {
+        if failures > 0 {
+            // Cut short by a shutdown, no more games are handed over then
+            let until = Instant::now() + PUBLISH_BACKOFF * 2u32.pow(failures.min(6) - 1);
+            while Instant::now() < until && !shutdown.is_requested() {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+        let Ok((req, receipt)) = msg_receiver.recv() else {
+            return;
+        };
+        if receipt.redelivered {
+            // The earlier attempt may still be running in another driver, only what a dead
+            // driver left behind is removed
+            GameDir::remove_stale(&req.game_id);
+        }
+        // A fresh backend per game, only the warm pool outlives it
+        let sandbox = sandbox::from_config(&config, pool.clone());
+        if let Err(e) = publisher.notify(create_executing_response(&req)) {
+            error!("Unable to publish that {} is executing: {e:?}", req.game_id);
+            receipt.requeue();
+            failures += 1;
+            continue;
+        }
+        let encoding = req.log_encoding.unwrap_or(config.log_encoding);
+        let publish_progress = |status| {
+            if let Err(e) = publisher.notify(status) {
+                warn!("Unable to publish progress: {e:?}");
+            }
+        };
+        let mut response = handler(req, &config, &sandbox, &publish_progress, &shutdown);
+        compression::encode_log(&mut response, encoding);
+        let game_id = response.game_id.clone();
+        match publisher.publish(response) {
+            Ok(()) => {
+                receipt.ack();
+                failures = 0;
+            }
+            Err(e) => {
+                error!("Unable to publish the result of {game_id}: {e:?}");
+                receipt.requeue();
+                failures += 1;
+            }
+        }
+    }
+}
 
-            let (player_process_out, sim_process_out) = match process1.process_type() {
-                ProcessType::Runner => (process1.output(), process2.output()),
-                ProcessType::Simulator => (process2.output(), process1.output()),
-            };
+const USAGE: &str = "Usage:
+    codecharacter-driver-2022                          consume game requests from RabbitMQ
+    codecharacter-driver-2022 run <request.json> [--log]
+                                                       execute a single GameRequest offline and
+                                                       print the GameStatus (or only its log)";
+
+/// Runs one GameRequest read from a file through the same pipeline as the consumer
+/// and prints the final GameStatus to stdout. Returns the process exit code.
+fn run_offline(args: &[String], config: &Arc<DriverConfig>) -> i32 {
+    let (path, log_only) = match args {
+        [path] => (path, false),
+        [path, flag] | [flag, path] if flag == "--log" => (path, true),
+        _ => {
+            eprintln!("{USAGE}");
+            return 2;
+        }
+    };
 
-            info!("Successfully executed for game {}", game_request.game_id);
-            cc_driver::create_final_response(game_request, player_process_out, sim_process_out)
+    let game_request = match std::fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read {path}: {e}"))
+        .and_then(|body| {
+            serde_json::from_str::<GameRequest>(&body)
+                .map_err(|e| format!("Invalid GameRequest in {path}: {e}"))
+        }) {
+        Ok(game_request) => game_request,
+        Err(e) => {
+            error!("{e}");
+            return 1;
         }
+    };
 
-        (Err(e), _) | (_, Err(e)) => create_error_response(&game_request, e),
-    }
-}
+    let encoding = game_request.log_encoding.unwrap_or(config.log_encoding);
+    let sandbox = sandbox::from_config(config, None);
+    let shutdown = match Shutdown::new() {
+        Ok(shutdown) => Arc::new(shutdown),
+        Err(e) => {
+            error!("{e:?}");
+            return 1;
+        }
+    };
+    let mut response = handler(game_request, config, &sandbox, &|_| {}, &shutdown);
+    let exit_code = match response.game_status {
+        GameStatusEnum::EXECUTED => 0,
+        _ => 1,
+    };
 
-fn worker_fn(msg_receiver: crossbeam_channel::Receiver<GameRequest>, publisher: Arc<Publisher>) {
-    while let Ok(req) = msg_receiver.recv() {
-        // publishing error means we can crash, something is wrong
-        publisher.publish(create_executing_response(&req)).unwrap();
-        let response = handler(req);
-        publisher.publish(response).unwrap();
+    if log_only {
+        if let Some(result) = &response.game_result {
+            print!("{}", result.log);
+        }
+    } else {
+        compression::encode_log(&mut response, encoding);
+        match serde_json::to_string_pretty(&response) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                error!("Unable to serialize the response: {e}");
+                return 1;
+            }
+        }
     }
+    exit_code
 }
 
 fn main() {
@@ -266,17 +653,65 @@ fn main() {
 
     let _handle = log4rs::init_config(config).unwrap();
 
+    let config = match DriverConfig::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    match args.first().map(String::as_str) {
+        None => {}
+        Some("run") => std::process::exit(run_offline(&args[1..], &config)),
+        Some(_) => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    }
+
+    // Before any other thread starts, they all have to leave the signals to it
+    let shutdown = match Shutdown::new().and_then(|shutdown| {
+        let shutdown = Arc::new(shutdown);
+        shutdown.listen()?;
+        Ok(shutdown)
+    }) {
+        Ok(shutdown) => shutdown,
+        Err(e) => {
+            error!("{e:?}");
+            std::process::exit(1);
+        }
+    };
+
+    // Before the pool fills up, its containers follow the naming scheme as well
+    sandbox::lifecycle::reap_from_config(&config);
+    GameDir::reap_stale();
+    let pool = WarmPool::from_config(&config);
+    let worker_shutdown = Arc::clone(&shutdown);
     let res = consumer(
-        env::var("RABBITMQ_HOST").unwrap(),
-        env::var("REQUEST_QUEUE").unwrap(),
-        env::var("RESPONSE_QUEUE").unwrap(),
-        worker_fn,
+        config,
+        Arc::clone(&shutdown),
+        move |receiver, publisher, config| {
+            worker_fn(
+                receiver,
+                publisher,
+                config,
+                pool.clone(),
+                Arc::clone(&worker_shutdown),
+            )
+        },
     );
 
     match res {
-        Ok(_) => {}
+        Ok(_) if shutdown.is_requested() => info!("Shut down"),
+        Ok(_) => {
+            error!("Stopped consuming without being asked to");
+            std::process::exit(1);
+        }
         Err(e) => {
             println!("{e}");
+            std::process::exit(1);
         }
     }
 }
@@ -0,0 +1,277 @@
+use std::{
+    io::{self, Read},
+    os::{
+        fd::{AsRawFd, RawFd},
+        linux::process::ChildExt,
+        unix::process::ExitStatusExt,
+    },
+    process::{Child, ExitStatus, Output},
+    sync::Mutex,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use nix::{
+    errno::Errno,
+    libc,
+    poll::{poll, PollFd, PollFlags},
+};
+
+use crate::{
+    config::memory_in_bytes,
+    response::{GameStatus, ProcessLimits, ProcessMetrics},
+    sandbox::{self, ContainerSpec},
+};
+
+/// What a finished process used. CPU time and peak memory are left out where they cannot
+/// be measured, e.g. for a container engine whose client is all `wait4` sees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub wall_time: Duration,
+    pub cpu_time: Option<Duration>,
+    pub peak_memory: Option<u64>,
+}
+
+/// Reaps `child` with `wait4`, measuring the wall time since `started` and the CPU time and
+/// peak resident memory of the child and the descendants it waited for
+pub fn wait(child: &Child, started: Instant) -> io::Result<(ExitStatus, Usage)> {
+    let mut status = 0;
+    // SAFETY: rusage is plain data, all zeroes is a valid value
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: both pointers are valid for the duration of the call
+        let pid = unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut rusage) };
+        if pid >= 0 {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    let time = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    Ok((
+        ExitStatus::from_raw(status),
+        Usage {
+            wall_time: started.elapsed(),
+            cpu_time: Some(time(rusage.ru_utime) + time(rusage.ru_stime)),
+            // Kilobytes on Linux
+            peak_memory: Some(rusage.ru_maxrss as u64 * 1024),
+        },
+    ))
+}
+
+/// Like `Child::wait_with_output`, reaping the child with `wait`. A child still running at
+/// `deadline`, or once `interrupt` becomes readable, is killed, its output is then `None`.
+pub fn wait_with_output(
+    mut child: Child,
+    started: Instant,
+    deadline: Instant,
+    interrupt: Option<RawFd>,
+) -> io::Result<(Option<Output>, Usage)> {
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+    let exited = wait_until(&child, deadline, interrupt)?;
+    if !exited {
+        let _ = sandbox::kill(&mut child);
+    }
+    let (status, usage) = wait(&child, started)?;
+    if !exited {
+        // The readers are left behind, a grandchild may still hold the pipes open
+        return Ok((None, usage));
+    }
+
+    let join = |reader: Option<JoinHandle<io::Result<Vec<u8>>>>| match reader {
+        Some(reader) => reader
+            .join()
+            .map_err(|_| io::Error::other("output reader panicked"))?,
+        None => Ok(vec![]),
+    };
+    Ok((
+        Some(Output {
+            status,
+            stdout: join(stdout)?,
+            stderr: join(stderr)?,
+        }),
+        usage,
+    ))
+}
+
+fn read_to_end(mut pipe: impl Read + Send + 'static) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = vec![];
+        pipe.read_to_end(&mut buf).map(|_| buf)
+    })
+}
+
+/// Whether `child` exited before `deadline` or `interrupt`, polling its pidfd
+fn wait_until(child: &Child, deadline: Instant, interrupt: Option<RawFd>) -> io::Result<bool> {
+    let pidfd = child.pidfd()?.as_raw_fd();
+    let mut fds = vec![PollFd::new(pidfd, PollFlags::POLLIN)];
+    fds.extend(interrupt.map(|fd| PollFd::new(fd, PollFlags::POLLIN)));
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        // Rounded up, so that the deadline has passed once poll times out
+        let timeout = left.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32;
+        match poll(&mut fds, timeout) {
+            Ok(ready) if ready > 0 => {
+                let exited = fds[0].revents().is_some_and(|r| !r.is_empty());
+                return Ok(exited);
+            }
+            Ok(_) if left.is_zero() => return Ok(false),
+            Ok(_) | Err(Errno::EINTR) => continue,
+            Err(e) => return Err(io::Error::from_raw_os_error(e as i32)),
+        }
+    }
+}
+
+/// `usage` of the process started for `spec`, next to the limits it ran under
+pub fn measure(tag: Option<String>, spec: &ContainerSpec, usage: Usage) -> ProcessMetrics {
+    let millis = |d: Duration| d.as_millis() as u64;
+    ProcessMetrics {
+        role: spec.role.clone(),
+        tag,
+        wall_time_ms: millis(usage.wall_time),
+        cpu_time_ms: usage.cpu_time.map(millis),
+        peak_memory_bytes: usage.peak_memory,
+        limits: ProcessLimits {
+            wall_time_ms: spec.limits.wall_time_limit.map(|s| s * 1000),
+            cpu_time_ms: spec.limits.cpu_time_limit * 1000,
+            memory_bytes: memory_in_bytes(&spec.limits.memory),
+        },
+    }
+}
+
+/// Metrics of every process of one game that has finished so far
+#[derive(Debug, Default)]
+pub struct GameMetrics {
+    processes: Mutex<Vec<ProcessMetrics>>,
+}
+
+impl GameMetrics {
+    pub fn record(&self, metrics: ProcessMetrics) {
+        self.processes.lock().unwrap().push(metrics);
+    }
+
+    /// Hands the metrics recorded so far to the result of `status`, if it has one
+    pub fn attach(&self, status: &mut GameStatus) {
+        if let Some(result) = &mut status.game_result {
+            result.metrics = std::mem::take(&mut *self.processes.lock().unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::linux::process::CommandExt,
+        process::{Command, Stdio},
+        time::{Duration, Instant},
+    };
+
+    use super::{measure, wait_with_output, Usage};
+    use crate::{
+        response::{ProcessLimits, ProcessMetrics},
+        utils::example_spec,
+    };
+
+    #[test]
+    fn measures_cpu_time_and_memory() {
+        let started = Instant::now();
+        // Busy for a while and touching about 32 MB
+        let child = Command::new("sh")
+            .args([
+                "-c",
+                "head -c 33554432 /dev/zero | tr '\\0' x | sort > /dev/null; exit 3",
+            ])
+            .create_pidfd(true)
+            .spawn()
+            .unwrap();
+        let deadline = started + Duration::from_secs(60);
+        let (output, usage) = wait_with_output(child, started, deadline, None).unwrap();
+        assert_eq!(output.unwrap().status.code(), Some(3));
+        assert!(usage.cpu_time.unwrap() > Duration::ZERO);
+        assert!(usage.wall_time >= usage.cpu_time.unwrap() / 8);
+        assert!(usage.peak_memory.unwrap() > 16 << 20);
+    }
+
+    #[test]
+    fn output_of_both_pipes() {
+        let child = Command::new("sh")
+            .args(["-c", "echo out; echo err >&2"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .create_pidfd(true)
+            .spawn()
+            .unwrap();
+        let started = Instant::now();
+        let deadline = started + Duration::from_secs(60);
+        let output = wait_with_output(child, started, deadline, None)
+            .unwrap()
+            .0
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+    }
+
+    #[test]
+    fn kills_at_the_deadline() {
+        let child = Command::new("sleep")
+            .arg("10")
+            .stdout(Stdio::piped())
+            .create_pidfd(true)
+            .spawn()
+            .unwrap();
+        let started = Instant::now();
+        let (output, usage) =
+            wait_with_output(child, started, started + Duration::from_millis(200), None).unwrap();
+        assert!(output.is_none());
+        assert!(usage.wall_time >= Duration::from_millis(200));
+        assert!(usage.wall_time < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn stops_when_interrupted() {
+        let child = Command::new("sleep")
+            .arg("10")
+            .create_pidfd(true)
+            .spawn()
+            .unwrap();
+        let (interrupt, writer) = nix::unistd::pipe().unwrap();
+        nix::unistd::write(writer, b"x").unwrap();
+        let started = Instant::now();
+        let deadline = started + Duration::from_secs(60);
+        let (output, usage) = wait_with_output(child, started, deadline, Some(interrupt)).unwrap();
+        let _ = nix::unistd::close(interrupt);
+        let _ = nix::unistd::close(writer);
+        assert!(output.is_none());
+        assert!(usage.wall_time < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn limits_next_to_usage() {
+        let mut spec = example_spec("cpp_runner");
+        spec.limits.wall_time_limit = Some(30);
+        let usage = Usage {
+            wall_time: Duration::from_millis(1500),
+            cpu_time: None,
+            peak_memory: Some(92 << 20),
+        };
+        assert_eq!(
+            measure(Some("attacker".to_owned()), &spec, usage),
+            ProcessMetrics {
+                role: "cpp_runner".to_owned(),
+                tag: Some("attacker".to_owned()),
+                wall_time_ms: 1500,
+                cpu_time_ms: None,
+                peak_memory_bytes: Some(92 << 20),
+                limits: ProcessLimits {
+                    wall_time_ms: Some(30_000),
+                    cpu_time_ms: 10_000,
+                    memory_bytes: Some(100 << 20),
+                },
+            }
+        );
+    }
+}
@@ -1,81 +1,341 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-use crate::{error::SimulatorError, request::GameRequest, response::GameStatus};
+use crate::{
+    config::DriverConfig, error::SimulatorError, request::GameRequest, response::GameStatus,
+    shutdown::Shutdown,
+};
 use amiquip::{
-    Channel, Connection, ConsumerMessage, ConsumerOptions, Exchange, Publish, QueueDeclareOptions,
-    Result,
+    AmqpProperties, Channel, Confirm, Connection, ConsumerMessage, ConsumerOptions, Delivery,
+    Exchange, Publish, QueueDeclareOptions, Result,
 };
+use crossbeam_channel::{select, Receiver, Sender};
+use log::{error, info, warn};
 
 const NUM_OF_THREADS: usize = 2;
 
-pub fn consumer(
-    url: String,
-    consumer_queue_name: String,
-    response_producer_queue_name: String,
-    handler_fn: fn(crossbeam_channel::Receiver<GameRequest>, Arc<Publisher>) -> (),
-) -> amiquip::Result<()> {
-    let mut connection = Connection::insecure_open(&url)?;
+/// How long a publish waits for the broker to confirm it
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Finished games remembered to drop duplicate deliveries of them
+const FINISHED_GAMES_REMEMBERED: usize = 1024;
+
+/// What becomes of a delivery once its worker is done with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Settlement {
+    Ack,
+    Requeue,
+    Reject,
+}
+
+/// Settles the delivery of a request handed to a worker. Only the consumer's channel can ack,
+/// so the worker sends its verdict back to the consumer thread.
+///
+/// A receipt dropped without being settled requeues the request. When it is dropped by a
+/// panicking worker and the request was already redelivered, the request is rejected instead,
+/// so that a request crashing the driver is not retried forever.
+pub struct Receipt {
+    delivery_tag: u64,
+    game_id: String,
+    /// The broker delivered the request before, a previous attempt may have crashed midway
+    pub redelivered: bool,
+    settle: Sender<(u64, String, Settlement)>,
+    settled: bool,
+}
+
+impl Receipt {
+    /// The final status of the game was published
+    pub fn ack(mut self) {
+        self.send(Settlement::Ack);
+    }
+
+    /// The game could not be run or its result not be published, another attempt may
+    pub fn requeue(mut self) {
+        self.send(Settlement::Requeue);
+    }
+
+    fn send(&mut self, settlement: Settlement) {
+        self.settled = true;
+        let _ = self
+            .settle
+            .send((self.delivery_tag, self.game_id.clone(), settlement));
+    }
+}
+
+impl Drop for Receipt {
+    fn drop(&mut self) {
+        if !self.settled {
+            self.send(if self.redelivered && std::thread::panicking() {
+                Settlement::Reject
+            } else {
+                Settlement::Requeue
+            });
+        }
+    }
+}
+
+/// Whether a game with some id is to be started
+#[derive(Debug, PartialEq, Eq)]
+pub enum Admission {
+    Start,
+    Running,
+    Finished,
+}
+
+/// The games of this driver that run or recently finished, so that a duplicate delivery of the
+/// same `game_id` is dropped instead of running the game twice at once or all over again.
+///
+/// The ledger only lives in memory. A request redelivered because a driver crashed, this one
+/// or another, is not known to it and runs again from scratch.
+#[derive(Debug, Default)]
+pub struct GameLedger {
+    running: HashSet<String>,
+    finished: HashSet<String>,
+    finish_order: VecDeque<String>,
+}
+
+impl GameLedger {
+    /// Marks `game_id` running if it neither runs nor finished
+    pub fn admit(&mut self, game_id: &str) -> Admission {
+        if self.running.contains(game_id) {
+            Admission::Running
+        } else if self.finished.contains(game_id) {
+            Admission::Finished
+        } else {
+            self.running.insert(game_id.to_owned());
+            Admission::Start
+        }
+    }
+
+    /// The final status of `game_id` was published
+    pub fn finish(&mut self, game_id: &str) {
+        self.running.remove(game_id);
+        if self.finished.insert(game_id.to_owned()) {
+            self.finish_order.push_back(game_id.to_owned());
+        }
+        if self.finish_order.len() > FINISHED_GAMES_REMEMBERED {
+            if let Some(oldest) = self.finish_order.pop_front() {
+                self.finished.remove(&oldest);
+            }
+        }
+    }
+
+    /// `game_id` stopped without a published result and may be started again
+    pub fn abandon(&mut self, game_id: &str) {
+        self.running.remove(game_id);
+    }
+}
+
+/// Hands every game request to one of the worker threads running `handler_fn`, until the
+/// broker ends the consumer or `shutdown` is requested.
+///
+/// A delivery is only acked once the worker published the final status of its game, so a
+/// crashing driver loses nothing. On shutdown the requests that were not started yet are
+/// nacked back to the queue, running games get `SHUTDOWN_GRACE_PERIOD` seconds before
+/// `shutdown` aborts them.
+pub fn consumer<F>(
+    config: Arc<DriverConfig>,
+    shutdown: Arc<Shutdown>,
+    handler_fn: F,
+) -> amiquip::Result<()>
+where
+    F: Fn(Receiver<(GameRequest, Receipt)>, Arc<Publisher>, Arc<DriverConfig>)
+        + Clone
+        + Send
+        + 'static,
+{
+    let mut connection = Connection::insecure_open(&config.rabbitmq_host)?;
 
     let channel = connection.open_channel(None)?;
 
     let queue = channel.queue_declare(
-        &consumer_queue_name,
+        &config.request_queue,
         QueueDeclareOptions {
             durable: true,
             ..Default::default()
         },
     )?;
 
+    // Unacked deliveries wait for their games, more than the workers can take would all be
+    // redelivered if the driver crashes
+    channel.qos(0, NUM_OF_THREADS as u16, false)?;
     let consumer = queue.consume(ConsumerOptions::default())?;
 
-    let response_publisher = Arc::new(Publisher::new(url, response_producer_queue_name).unwrap());
+    let response_publisher = Arc::new(
+        Publisher::new(config.rabbitmq_host.clone(), config.response_queue.clone()).unwrap(),
+    );
 
-    let (s, r) = crossbeam_channel::bounded(NUM_OF_THREADS);
+    // Without a buffer a request is only handed over to a worker that starts it right away
+    let (s, r) = crossbeam_channel::bounded(0);
+    // Disconnects once the consumer stopped and every receipt was settled
+    let (settle, settlements) = crossbeam_channel::unbounded();
 
     // each thread has a receiver
     let mut threads = vec![];
     for _ in 0..NUM_OF_THREADS {
         let new_r = r.clone();
         let publisher_clone = Arc::clone(&response_publisher);
+        let config_clone = Arc::clone(&config);
+        let handler_fn = handler_fn.clone();
         threads.push(std::thread::spawn(move || {
-            handler_fn(new_r, publisher_clone)
+            handler_fn(new_r, publisher_clone, config_clone)
         }))
     }
 
-    for message in consumer.receiver().iter() {
-        match message {
-            ConsumerMessage::Delivery(delivery) => {
-                let body_str = String::from_utf8_lossy(&delivery.body);
-                let res: Result<GameRequest, serde_json::Error> = serde_json::from_str(&body_str);
-                match res {
-                    Ok(match_request) => {
-                        s.send(match_request).unwrap();
-                        consumer.ack(delivery)?;
-                    }
-                    Err(e) => {
-                        eprintln!("{e:?}");
+    let mut ledger = GameLedger::default();
+    let mut unsettled: HashMap<u64, Delivery> = HashMap::new();
+    let settle_delivery = |unsettled: &mut HashMap<u64, Delivery>,
+                           ledger: &mut GameLedger,
+                           (delivery_tag, game_id, settlement): (u64, String, Settlement)|
+     -> amiquip::Result<()> {
+        match settlement {
+            Settlement::Ack => ledger.finish(&game_id),
+            Settlement::Requeue | Settlement::Reject => ledger.abandon(&game_id),
+        }
+        let delivery = match unsettled.remove(&delivery_tag) {
+            Some(delivery) => delivery,
+            None => return Ok(()),
+        };
+        match settlement {
+            Settlement::Ack => consumer.ack(delivery),
+            Settlement::Requeue => consumer.nack(delivery, true),
+            Settlement::Reject => {
+                warn!("Dropping request of game {game_id}, it failed before");
+                consumer.reject(delivery, false)
+            }
+        }
+    };
+
+    loop {
+        select! {
+            recv(consumer.receiver()) -> message => match message {
+                Ok(ConsumerMessage::Delivery(delivery)) => {
+                    let body_str = String::from_utf8_lossy(&delivery.body);
+                    let res: Result<GameRequest, serde_json::Error> =
+                        serde_json::from_str(&body_str);
+                    match res {
+                        Ok(match_request) => {
+                            let game_id = match_request.game_id.clone();
+                            match ledger.admit(&game_id) {
+                                Admission::Start => {}
+                                admission => {
+                                    info!(
+                                        "Dropping duplicate request of game {game_id}, \
+                                         it is {admission:?}"
+                                    );
+                                    consumer.ack(delivery)?;
+                                    continue;
+                                }
+                            }
+                            if delivery.redelivered {
+                                info!("Request of game {game_id} was delivered before");
+                            }
+                            let receipt = Receipt {
+                                delivery_tag: delivery.delivery_tag(),
+                                game_id: game_id.clone(),
+                                redelivered: delivery.redelivered,
+                                settle: settle.clone(),
+                                settled: false,
+                            };
+                            unsettled.insert(delivery.delivery_tag(), delivery);
+                            // A request that is not handed over drops its receipt, which requeues it
+                            select! {
+                                send(s, (match_request, receipt)) -> res => if res.is_err() {
+                                    error!("Every worker stopped, no longer consuming");
+                                    break;
+                                },
+                                recv(shutdown.requests()) -> _ => break,
+                            }
+                        }
+                        Err(e) => {
+                            // Would hold a prefetch slot forever and can never be parsed
+                            error!("Rejecting a request that could not be parsed: {e}");
+                            consumer.reject(delivery, false)?;
+                        }
                     }
                 }
+                other => {
+                    error!("Consumer ended: {other:?}");
+                    break;
+                }
+            },
+            recv(settlements) -> settlement => {
+                settle_delivery(&mut unsettled, &mut ledger, settlement.unwrap())?;
             }
-            other => {
-                println!("Consumer ended: {other:?}");
-                break;
+            recv(shutdown.requests()) -> _ => break,
+        }
+    }
+    drop(s);
+    drop(settle);
+
+    if shutdown.is_requested() {
+        consumer.cancel()?;
+        // Prefetched by the consumer but never handed to a worker
+        for message in consumer.receiver().try_iter() {
+            if let ConsumerMessage::Delivery(delivery) = message {
+                consumer.nack(delivery, true)?;
             }
         }
+        info!(
+            "Stopped consuming, running games get {} seconds to finish",
+            config.shutdown_grace_period
+        );
+    }
+    // Running games settle their deliveries as they finish
+    let grace = Instant::now() + Duration::from_secs(config.shutdown_grace_period);
+    loop {
+        let grace_over = if shutdown.is_requested() && !shutdown.is_aborted() {
+            crossbeam_channel::at(grace)
+        } else {
+            crossbeam_channel::never()
+        };
+        select! {
+            recv(settlements) -> settlement => match settlement {
+                Ok(settlement) => settle_delivery(&mut unsettled, &mut ledger, settlement)?,
+                Err(_) => break,
+            },
+            recv(grace_over) -> _ => {
+                info!("Grace period is over, stopping running games");
+                shutdown.abort();
+            }
+        }
+    }
+    for thread in threads {
+        let _ = thread.join();
     }
 
     connection.close()
 }
 
+/// Publishes the statuses of games to the response queue.
+///
+/// The final status of a game is a persistent message that the broker has to confirm before
+/// its request is acked. Everything before it (`EXECUTING`, progress) is sent without waiting,
+/// so a slow broker never holds up running games. Both kinds have a connection of their own,
+/// a connection that failed is reopened on the next publish.
 pub struct Publisher {
-    connection: Option<Connection>,
-    channel: Mutex<Channel>,
+    url: String,
     queue_name: String,
+    confirmed: Mutex<Option<Link>>,
+    unconfirmed: Mutex<Option<Link>>,
 }
 
-impl Publisher {
-    pub fn new(url: String, queue_name: String) -> Result<Self, SimulatorError> {
-        let mut connection = Connection::insecure_open(&url).map_err(|e| {
+struct Link {
+    channel: Channel,
+    /// Only for the confirmed link
+    confirms: Option<Receiver<Confirm>>,
+    /// Delivery tag of the last message published, the broker counts from 1
+    published: u64,
+    // Dropped after the channel, which closes the connection
+    _connection: Connection,
+}
+
+impl Link {
+    fn open(url: &str, queue_name: &str, confirmed: bool) -> Result<Self, SimulatorError> {
+        let mut connection = Connection::insecure_open(url).map_err(|e| {
             SimulatorError::UnidentifiedError(format!(
                 "Error in opening connection to publish queue [Connection::insecure_open]: {e}"
             ))
@@ -87,9 +347,23 @@ impl Publisher {
             ))
         })?;
 
+        let confirms = if confirmed {
+            let confirms = channel
+                .listen_for_publisher_confirms()
+                .and_then(|confirms| channel.enable_publisher_confirms().map(|_| confirms))
+                .map_err(|e| {
+                    SimulatorError::UnidentifiedError(format!(
+                        "Error in enabling publisher confirms [Publisher::new]: {e}"
+                    ))
+                })?;
+            Some(confirms)
+        } else {
+            None
+        };
+
         channel
             .queue_declare(
-                &queue_name,
+                queue_name,
                 QueueDeclareOptions {
                     durable: true,
                     ..Default::default()
@@ -101,33 +375,216 @@ impl Publisher {
                 ))
             })?;
 
-        Ok(Self {
-            connection: Some(connection),
-            channel: Mutex::new(channel),
-            queue_name,
+        Ok(Link {
+            channel,
+            confirms,
+            published: 0,
+            _connection: connection,
         })
     }
-    pub fn publish(&self, response: GameStatus) -> Result<(), SimulatorError> {
-        let channel = self.channel.lock().unwrap();
-        let exchange = Exchange::direct(&channel);
-        let body = serde_json::to_string(&response)
-            .map_err(|e| SimulatorError::UnidentifiedError(format!("{e}")))?;
-        exchange
-            .publish(Publish::new(body.as_bytes(), &self.queue_name))
+
+    /// Returns the delivery tag of the message
+    fn send(
+        &mut self,
+        queue_name: &str,
+        body: &str,
+        properties: AmqpProperties,
+    ) -> Result<u64, SimulatorError> {
+        Exchange::direct(&self.channel)
+            .publish(Publish::with_properties(
+                body.as_bytes(),
+                queue_name,
+                properties,
+            ))
             .map_err(|e| {
                 SimulatorError::UnidentifiedError(format!(
                     "Error in publishing to the queue[Publisher::publish]{e}"
                 ))
             })?;
-        Ok(())
+        self.published += 1;
+        Ok(self.published)
     }
 }
 
-impl Drop for Publisher {
-    fn drop(&mut self) {
-        if self.connection.is_some() {
-            let conn = self.connection.take().unwrap();
-            let _ = conn.close();
+/// Whether `confirm` acks (`Some(true)`) or nacks (`Some(false)`) the message published with
+/// `delivery_tag`, `None` when it is about other messages
+fn confirmation(confirm: Confirm, delivery_tag: u64) -> Option<bool> {
+    let (payload, acked) = match confirm {
+        Confirm::Ack(payload) => (payload, true),
+        Confirm::Nack(payload) => (payload, false),
+    };
+    let covered = payload.delivery_tag == delivery_tag
+        || (payload.multiple && payload.delivery_tag > delivery_tag);
+    covered.then_some(acked)
+}
+
+impl Publisher {
+    pub fn new(url: String, queue_name: String) -> Result<Self, SimulatorError> {
+        let confirmed = Link::open(&url, &queue_name, true)?;
+        let unconfirmed = Link::open(&url, &queue_name, false)?;
+        Ok(Self {
+            url,
+            queue_name,
+            confirmed: Mutex::new(Some(confirmed)),
+            unconfirmed: Mutex::new(Some(unconfirmed)),
+        })
+    }
+
+    /// Runs `f` on the link, opening it first if an earlier publish failed on it
+    fn with_link<T>(
+        &self,
+        link: &Mutex<Option<Link>>,
+        confirmed: bool,
+        f: impl FnOnce(&mut Link) -> Result<T, SimulatorError>,
+    ) -> Result<T, SimulatorError> {
+        let mut link = link.lock().unwrap();
+        if link.is_none() {
+            *link = Some(Link::open(&self.url, &self.queue_name, confirmed)?);
+        }
+        let result = f(link.as_mut().unwrap());
+        if result.is_err() {
+            *link = None;
+        }
+        result
+    }
+
+    /// Returns once the broker confirmed that it took `response`, the final status of a game
+    pub fn publish(&self, response: GameStatus) -> Result<(), SimulatorError> {
+        let body = serde_json::to_string(&response)
+            .map_err(|e| SimulatorError::UnidentifiedError(format!("{e}")))?;
+        self.with_link(&self.confirmed, true, |link| {
+            let delivery_tag = link.send(
+                &self.queue_name,
+                &body,
+                AmqpProperties::default().with_delivery_mode(2),
+            )?;
+
+            // Confirms of earlier messages that timed out may still arrive first
+            let confirms = link.confirms.as_ref().unwrap();
+            let deadline = Instant::now() + CONFIRM_TIMEOUT;
+            loop {
+                let confirm = confirms.recv_deadline(deadline).map_err(|e| {
+                    SimulatorError::UnidentifiedError(format!(
+                        "No publisher confirm for {} [Publisher::publish]: {e}",
+                        response.game_id
+                    ))
+                })?;
+                match confirmation(confirm, delivery_tag) {
+                    Some(true) => return Ok(()),
+                    Some(false) => {
+                        return Err(SimulatorError::UnidentifiedError(format!(
+                            "The broker rejected the status of {} [Publisher::publish]",
+                            response.game_id
+                        )))
+                    }
+                    None => {}
+                }
+            }
+        })
+    }
+
+    /// Sends a status that precedes the final one, without waiting for the broker
+    pub fn notify(&self, status: GameStatus) -> Result<(), SimulatorError> {
+        let body = serde_json::to_string(&status)
+            .map_err(|e| SimulatorError::UnidentifiedError(format!("{e}")))?;
+        self.with_link(&self.unconfirmed, false, |link| {
+            link.send(&self.queue_name, &body, AmqpProperties::default())
+                .map(|_| ())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amiquip::{Confirm, ConfirmPayload};
+
+    use super::{
+        confirmation, Admission, GameLedger, Receipt, Settlement, FINISHED_GAMES_REMEMBERED,
+    };
+
+    #[test]
+    fn unsettled_receipts_requeue() {
+        let (settle, settlements) = crossbeam_channel::unbounded();
+        let receipt = |delivery_tag, redelivered| Receipt {
+            delivery_tag,
+            game_id: delivery_tag.to_string(),
+            redelivered,
+            settle: settle.clone(),
+            settled: false,
+        };
+
+        receipt(1, false).ack();
+        receipt(2, true).requeue();
+        drop(receipt(3, true));
+        let crashing = receipt(4, false);
+        let _ = std::thread::spawn(move || {
+            let _receipt = crashing;
+            panic!("worker crashed");
+        })
+        .join();
+        // Crashed the driver twice now
+        let crashing = receipt(5, true);
+        let _ = std::thread::spawn(move || {
+            let _receipt = crashing;
+            panic!("worker crashed");
+        })
+        .join();
+
+        let settled = settlements
+            .try_iter()
+            .map(|(tag, _, settlement)| (tag, settlement))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            settled,
+            vec![
+                (1, Settlement::Ack),
+                (2, Settlement::Requeue),
+                (3, Settlement::Requeue),
+                (4, Settlement::Requeue),
+                (5, Settlement::Reject),
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_games_are_not_admitted() {
+        let mut ledger = GameLedger::default();
+        assert_eq!(ledger.admit("1"), Admission::Start);
+        assert_eq!(ledger.admit("1"), Admission::Running);
+        assert_eq!(ledger.admit("2"), Admission::Start);
+
+        ledger.finish("1");
+        assert_eq!(ledger.admit("1"), Admission::Finished);
+
+        // Requeued games can start again
+        ledger.abandon("2");
+        assert_eq!(ledger.admit("2"), Admission::Start);
+    }
+
+    #[test]
+    fn forgets_the_oldest_finished_games() {
+        let mut ledger = GameLedger::default();
+        for id in 0..=FINISHED_GAMES_REMEMBERED {
+            assert_eq!(ledger.admit(&id.to_string()), Admission::Start);
+            ledger.finish(&id.to_string());
         }
+        assert_eq!(ledger.admit("0"), Admission::Start);
+        assert_eq!(ledger.admit("1"), Admission::Finished);
+    }
+
+    #[test]
+    fn confirms_cover_their_messages() {
+        let payload = |delivery_tag, multiple| ConfirmPayload {
+            delivery_tag,
+            multiple,
+        };
+        assert_eq!(confirmation(Confirm::Ack(payload(3, false)), 3), Some(true));
+        assert_eq!(
+            confirmation(Confirm::Nack(payload(3, false)), 3),
+            Some(false)
+        );
+        assert_eq!(confirmation(Confirm::Ack(payload(5, true)), 3), Some(true));
+        assert_eq!(confirmation(Confirm::Ack(payload(2, true)), 3), None);
+        assert_eq!(confirmation(Confirm::Ack(payload(4, false)), 3), None);
     }
 }
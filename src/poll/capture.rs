@@ -0,0 +1,245 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::config::DriverConfig;
+
+use super::epoll_entry::ProcessType;
+
+/// Bounded capture of one process' stderr.
+///
+/// The first quarter of `limit` bytes is kept as the head and the last three quarters as the
+/// tail, so a user always sees how their game started and how it ended. Everything in between
+/// is replaced by a `TRUNCATED, {n} bytes` line. Once the tail outgrows `spill_threshold` it
+/// moves from memory into a ring buffer file at `spill_path`, which is removed again on drop.
+pub struct LogCapture {
+    head_limit: usize,
+    head: Vec<u8>,
+    tail: Tail,
+    total: u64,
+}
+
+impl LogCapture {
+    pub fn new(limit: usize, spill_threshold: usize, spill_path: PathBuf) -> Self {
+        let head_limit = limit / 4;
+        LogCapture {
+            head_limit,
+            head: vec![],
+            tail: Tail {
+                limit: limit - head_limit,
+                spill_threshold,
+                spill_path,
+                memory: VecDeque::new(),
+                file: None,
+                len: 0,
+                pos: 0,
+            },
+            total: 0,
+        }
+    }
+
+    /// `MAX_LOG_SIZE` for runners and `MAX_SIMULATOR_LOG_SIZE` for the simulator, spilling
+    /// into `{dir}/{tag}.stderr` (or `simulator.stderr`)
+    pub fn for_process(config: &DriverConfig, process_type: &ProcessType, dir: &Path) -> Self {
+        let (limit, name) = match process_type {
+            ProcessType::Runner(tag) => (config.max_log_size, tag.as_str()),
+            ProcessType::Simulator => (config.max_simulator_log_size, "simulator"),
+        };
+        LogCapture::new(
+            limit,
+            config.log_spill_threshold,
+            dir.join(format!("{name}.stderr")),
+        )
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> io::Result<()> {
+        self.total += data.len() as u64;
+        let to_head = data.len().min(self.head_limit - self.head.len());
+        self.head.extend_from_slice(&data[..to_head]);
+        self.tail.push(&data[to_head..])
+    }
+
+    /// Head and tail joined, with a truncation marker in between if anything was dropped.
+    /// Both ends are trimmed to whole lines so that no half line ends up next to the marker.
+    pub fn finish(mut self) -> io::Result<String> {
+        let tail = self.tail.contents()?;
+        let dropped = self.total - (self.head.len() + tail.len()) as u64;
+        if dropped == 0 {
+            let mut all = std::mem::take(&mut self.head);
+            all.extend_from_slice(&tail);
+            return Ok(String::from_utf8_lossy(&all).into_owned());
+        }
+
+        let head_end = self
+            .head
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |i| i + 1);
+        let tail_start = tail
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(tail.len(), |i| i + 1);
+        let omitted = dropped + (self.head.len() - head_end + tail_start) as u64;
+
+        let mut log = String::from_utf8_lossy(&self.head[..head_end]).into_owned();
+        log.push_str(&format!("TRUNCATED, {omitted} bytes\n"));
+        log.push_str(&String::from_utf8_lossy(&tail[tail_start..]));
+        Ok(log)
+    }
+}
+
+/// The last `limit` bytes, in memory or in a ring buffer file
+struct Tail {
+    limit: usize,
+    spill_threshold: usize,
+    spill_path: PathBuf,
+    memory: VecDeque<u8>,
+    file: Option<File>,
+    /// Bytes used in the file and where the next write goes
+    len: usize,
+    pos: usize,
+}
+
+impl Tail {
+    fn push(&mut self, data: &[u8]) -> io::Result<()> {
+        let data = &data[data.len().saturating_sub(self.limit)..];
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                self.memory.extend(data);
+                let excess = self.memory.len().saturating_sub(self.limit);
+                self.memory.drain(..excess);
+                if self.memory.len() > self.spill_threshold {
+                    self.spill()?;
+                }
+                return Ok(());
+            }
+        };
+
+        let first = data.len().min(self.limit - self.pos);
+        file.seek(SeekFrom::Start(self.pos as u64))?;
+        file.write_all(&data[..first])?;
+        if first < data.len() {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&data[first..])?;
+        }
+        self.pos = (self.pos + data.len()) % self.limit;
+        self.len = (self.len + data.len()).min(self.limit);
+        Ok(())
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.spill_path)?;
+        let (front, back) = self.memory.as_slices();
+        file.write_all(front)?;
+        file.write_all(back)?;
+        self.len = self.memory.len();
+        self.pos = self.len % self.limit;
+        self.memory = VecDeque::new();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn contents(&mut self) -> io::Result<Vec<u8>> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return Ok(self.memory.iter().copied().collect()),
+        };
+        let mut contents = vec![0; self.len];
+        if self.len < self.limit {
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(&mut contents)?;
+        } else {
+            // The oldest byte sits right where the next write would go
+            let older = self.limit - self.pos;
+            file.seek(SeekFrom::Start(self.pos as u64))?;
+            file.read_exact(&mut contents[..older])?;
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(&mut contents[older..])?;
+        }
+        Ok(contents)
+    }
+}
+
+impl Drop for Tail {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.spill_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogCapture;
+    use crate::utils::TestDir;
+
+    #[test]
+    fn short_logs_are_kept_whole() {
+        let dir = TestDir::new("capture_short_logs_are_kept_whole");
+        let mut capture = LogCapture::new(100, 1000, dir.join("stderr"));
+        capture.push(b"TURN 1\nhello\n").unwrap();
+        capture.push(b"ENDLOG\n").unwrap();
+        assert_eq!(capture.finish().unwrap(), "TURN 1\nhello\nENDLOG\n");
+    }
+
+    #[test]
+    fn characters_split_across_pushes() {
+        let dir = TestDir::new("capture_characters_split_across_pushes");
+        let mut capture = LogCapture::new(100, 1000, dir.join("stderr"));
+        let text = "caf\u{e9} \u{1f680}\n".as_bytes();
+        for byte in text {
+            capture.push(&[*byte]).unwrap();
+        }
+        capture.push(&[0xff, b'\n']).unwrap();
+        assert_eq!(capture.finish().unwrap(), "caf\u{e9} \u{1f680}\n\u{fffd}\n");
+    }
+
+    #[test]
+    fn keeps_head_and_tail_lines() {
+        let dir = TestDir::new("capture_keeps_head_and_tail_lines");
+        let mut capture = LogCapture::new(40, 1000, dir.join("stderr"));
+        for turn in 1..=20 {
+            capture.push(format!("turn {turn}\n").as_bytes()).unwrap();
+        }
+        // 10 head bytes and 30 tail bytes, trimmed to whole lines
+        assert_eq!(
+            capture.finish().unwrap(),
+            "turn 1\nTRUNCATED, 120 bytes\nturn 18\nturn 19\nturn 20\n"
+        );
+    }
+
+    #[test]
+    fn spills_the_tail_to_disk() {
+        let dir = TestDir::new("capture_spills_the_tail_to_disk");
+        let spill = dir.join("stderr");
+        let mut capture = LogCapture::new(40, 8, spill.clone());
+        capture.push(b"first\n").unwrap();
+        capture.push(b"0123456789abcdef\n").unwrap();
+        assert!(spill.exists());
+        for turn in 1..=20 {
+            capture.push(format!("turn {turn}\n").as_bytes()).unwrap();
+        }
+        // A single write larger than the whole ring
+        capture.push(&[b'x'; 50]).unwrap();
+        capture.push(b"\nlast line\n").unwrap();
+
+        assert_eq!(
+            capture.finish().unwrap(),
+            "first\nTRUNCATED, 219 bytes\nlast line\n"
+        );
+        assert!(!spill.exists());
+    }
+}
@@ -1,25 +1,35 @@
 use nix::sys::epoll::EpollFlags;
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
 
 use crate::error::EpollError;
 
-use std::env;
-use std::io::Read;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
 use std::os::fd::AsRawFd;
 use std::os::linux::process::ChildExt;
 use std::process::ChildStderr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::error::SimulatorError;
+use crate::metrics::{self, Usage};
+use crate::progress::SimulatorProgress;
+use crate::sandbox;
+use crate::shutdown::Shutdown;
 
 use std::process::ExitStatus;
 
 use std::process::Child;
 
+use super::capture::LogCapture;
 use super::epoll::CallbackMessage;
 use super::epoll::Pollable;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ProcessType {
-    Runner,
+    /// A player submission, tagged with its participant (e.g. `player` or `defender`)
+    Runner(String),
     Simulator,
 }
 
@@ -27,6 +37,9 @@ pub enum ProcessType {
 pub struct Process {
     process: Child,
     process_type: ProcessType,
+    /// Kept open for as long as the process runs, e.g. FIFO ends opened by path in the container
+    _held: Vec<File>,
+    started: Instant,
 }
 
 impl Process {
@@ -34,6 +47,18 @@ impl Process {
         Process {
             process: proc,
             process_type: proc_type,
+            _held: vec![],
+            started: Instant::now(),
+        }
+    }
+
+    /// Like `new`, closing `held` only once the process entry is dropped
+    pub fn holding(proc: Child, proc_type: ProcessType, held: Vec<File>) -> Self {
+        Process {
+            process: proc,
+            process_type: proc_type,
+            _held: held,
+            started: Instant::now(),
         }
     }
 
@@ -45,78 +70,121 @@ impl Process {
         &self.process_type
     }
 
-    pub fn wait(&mut self) -> Result<ExitStatus, SimulatorError> {
-        self.process.wait().map_err(|err| {
+    /// Reaps the process, measuring what it used since it was registered
+    pub fn wait(&mut self) -> Result<(ExitStatus, Usage), SimulatorError> {
+        metrics::wait(&self.process, self.started).map_err(|err| {
             SimulatorError::UnidentifiedError(format!("Waiting on Child Failed: {err}"))
         })
     }
 
     pub fn kill(&mut self) {
-        let _ = self.process.kill();
+        let _ = sandbox::kill(&mut self.process);
     }
 }
 
 pub struct ProcessOutput {
     stderr: ChildStderr,
-    output: String,
+    output: LogCapture,
     process_type: ProcessType,
+    /// Sees every chunk as it is read, for progress updates
+    observer: Option<Arc<Mutex<SimulatorProgress>>>,
 }
 
+/// Bytes read from the pipe per call
+const READ_CHUNK: usize = 64 * 1024;
+
 impl ProcessOutput {
-    pub fn new(stderr: ChildStderr, proc_type: ProcessType) -> Self {
+    pub fn new(stderr: ChildStderr, proc_type: ProcessType, capture: LogCapture) -> Self {
         ProcessOutput {
             stderr,
-            output: String::new(),
+            output: capture,
             process_type: proc_type,
+            observer: None,
         }
     }
 
+    pub fn observed_by(mut self, observer: Arc<Mutex<SimulatorProgress>>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
     pub fn stderr(&self) -> &ChildStderr {
         &self.stderr
     }
 
-    pub fn output(self) -> String {
-        self.output
+    pub fn output(self) -> Result<String, SimulatorError> {
+        self.output.finish().map_err(|err| {
+            SimulatorError::UnidentifiedError(format!("Error during log extraction: {err}"))
+        })
     }
 
     pub fn process_type(&self) -> &ProcessType {
         &self.process_type
     }
 
-    pub fn read_to_string(&mut self) -> Result<(), SimulatorError> {
-        let mut buf = String::new();
+    /// Moves one chunk from the pipe into the capture. Only raw bytes are captured, they are
+    /// decoded (lossily) once the process is done, so a multi byte character split across
+    /// two reads or bytes that are not UTF-8 at all never fail the game.
+    /// Returns the number of bytes read, 0 at the end of the output.
+    pub fn read_chunk(&mut self) -> Result<usize, SimulatorError> {
         let map_err =
             |err| SimulatorError::UnidentifiedError(format!("Error during log extraction: {err}"));
 
-        match self.process_type {
-            ProcessType::Runner => {
-                let limit: usize = env::var("MAX_LOG_SIZE").unwrap().parse().unwrap();
-                let stderr = &mut self.stderr;
-
-                let _ = stderr
-                    .take(limit as u64)
-                    .read_to_string(&mut buf)
-                    .map_err(map_err)?;
-                if self.output.len() < limit {
-                    let rem = limit - self.output.len();
-                    buf = buf.chars().take(rem).collect::<String>();
-                } else {
-                    buf = String::new();
-                }
-            }
-            ProcessType::Simulator => {
-                let _ = self.stderr.read_to_string(&mut buf).map_err(map_err)?;
+        let mut buf = [0; READ_CHUNK];
+        let read = loop {
+            match self.stderr.read(&mut buf) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                other => break other.map_err(map_err)?,
             }
+        };
+        self.output.push(&buf[..read]).map_err(map_err)?;
+        if let Some(observer) = &self.observer {
+            observer.lock().unwrap().feed(&buf[..read]);
         }
+        Ok(read)
+    }
 
-        self.output.push_str(buf.as_str());
+    /// Reads until the process closes its stderr
+    pub fn drain(&mut self) -> Result<(), SimulatorError> {
+        while self.read_chunk()? > 0 {}
         Ok(())
     }
 }
 
+/// One shot timer for the wall clock time of a phase
+pub struct Deadline {
+    timer: TimerFd,
+    limit: Duration,
+}
+
+impl Deadline {
+    pub fn after(limit: Duration) -> Result<Self, SimulatorError> {
+        let map_err =
+            |err| SimulatorError::UnidentifiedError(format!("Unable to set deadline: {err}"));
+        let timer =
+            TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::TFD_CLOEXEC).map_err(map_err)?;
+        // A zero expiration would disarm the timer instead
+        let expiration = limit.max(Duration::from_millis(1));
+        timer
+            .set(
+                Expiration::OneShot(TimeSpec::from(expiration)),
+                TimerSetTimeFlags::empty(),
+            )
+            .map_err(map_err)?;
+        Ok(Deadline { timer, limit })
+    }
+
+    pub fn limit(&self) -> Duration {
+        self.limit
+    }
+}
+
 pub enum EpollEntryType {
     Process(Process),
     StdErr(ProcessOutput),
+    Deadline(Deadline),
+    /// Readable once the driver shuts down and the game has to give up
+    Shutdown(Arc<Shutdown>),
 }
 
 impl Pollable for EpollEntryType {
@@ -128,6 +196,8 @@ impl Pollable for EpollEntryType {
                 .expect("PidFd should be extractable from Child, make sure the Command is invoked correctly")
                 .as_raw_fd(),
             EpollEntryType::StdErr(e) => e.stderr().as_raw_fd(),
+            EpollEntryType::Deadline(d) => d.timer.as_raw_fd(),
+            EpollEntryType::Shutdown(s) => s.abort_fd(),
         }
     }
     fn process_event(
@@ -137,19 +207,54 @@ impl Pollable for EpollEntryType {
         let fd = event.data();
         let flags = event.events();
         match self {
-            EpollEntryType::Process(_) => Ok(CallbackMessage::HandleExplicitly(self.get_fd())),
+            EpollEntryType::Process(_)
+            | EpollEntryType::Deadline(_)
+            | EpollEntryType::Shutdown(_) => Ok(CallbackMessage::HandleExplicitly(self.get_fd())),
             EpollEntryType::StdErr(output) => {
-                let mut message = CallbackMessage::Nop;
-                if flags.contains(EpollFlags::EPOLLIN) {
-                    output
-                        .read_to_string()
-                        .map_err(|e| EpollError::EpollCallbackError(format!("{e:?}")))?;
-                }
+                let map_err = |e| EpollError::EpollCallbackError(format!("{e:?}"));
+                // A single read never blocks after EPOLLIN, and once the writer hung up
+                // whatever is left in the pipe can be read without waiting
                 if flags.contains(EpollFlags::EPOLLHUP) {
-                    message = CallbackMessage::Unregister(fd as i32);
+                    output.drain().map_err(map_err)?;
+                    return Ok(CallbackMessage::Unregister(fd as i32));
                 }
-                Ok(message)
+                if flags.contains(EpollFlags::EPOLLIN) {
+                    output.read_chunk().map_err(map_err)?;
+                }
+                Ok(CallbackMessage::Nop)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Command, Stdio};
+
+    use super::{ProcessOutput, ProcessType};
+    use crate::{poll::capture::LogCapture, utils::TestDir};
+
+    #[test]
+    fn invalid_utf8_is_replaced_not_fatal() {
+        let dir = TestDir::new("epoll_entry_invalid_utf8_is_replaced_not_fatal");
+        let mut child = Command::new("sh")
+            .args([
+                "-c",
+                r"printf 'TURN 1\n\377\376garbage\n\303' >&2; printf '\251\n' >&2",
+            ])
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut output = ProcessOutput::new(
+            child.stderr.take().unwrap(),
+            ProcessType::Runner("player".to_owned()),
+            LogCapture::new(1000, 1000, dir.join("stderr")),
+        );
+        output.drain().unwrap();
+        child.wait().unwrap();
+        assert_eq!(
+            output.output().unwrap(),
+            "TURN 1\n\u{fffd}\u{fffd}garbage\n\u{e9}\n"
+        );
+    }
+}
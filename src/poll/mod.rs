@@ -1,2 +1,3 @@
+pub mod capture;
 pub mod epoll;
 pub mod epoll_entry;
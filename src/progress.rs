@@ -0,0 +1,203 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    create_progress_response,
+    request::GameRequest,
+    response::{GamePhase, GameProgress, GameStatus},
+};
+
+/// Longer simulator lines are skipped, they cannot be `TURN` or `DESTRUCTION`
+const MAX_LINE: usize = 256;
+
+/// Turn and destruction seen so far in the simulator's stderr, fed chunk by chunk as the
+/// epoll loop reads it
+#[derive(Debug, Default)]
+pub struct SimulatorProgress {
+    partial: Vec<u8>,
+    skipping: bool,
+    pub turn: Option<usize>,
+    pub destruction: Option<f64>,
+}
+
+impl SimulatorProgress {
+    pub fn feed(&mut self, data: &[u8]) {
+        for chunk in data.split_inclusive(|b| *b == b'\n') {
+            let complete = chunk.ends_with(b"\n");
+            if !self.skipping {
+                self.partial.extend_from_slice(chunk);
+                if self.partial.len() > MAX_LINE {
+                    self.partial.clear();
+                    self.skipping = true;
+                }
+            }
+            if complete {
+                if !self.skipping {
+                    let line = std::mem::take(&mut self.partial);
+                    self.line(&String::from_utf8_lossy(&line));
+                }
+                self.skipping = false;
+            }
+        }
+    }
+
+    fn line(&mut self, ln: &str) {
+        let ln = ln.trim();
+        if let Some(turn) = ln
+            .strip_prefix("TURN, ")
+            .and_then(|x| x.parse::<usize>().ok())
+        {
+            self.turn = Some(turn);
+        } else if let Some(destruction) = ln
+            .strip_prefix("DESTRUCTION, ")
+            .and_then(|s| s.strip_suffix('%'))
+            .and_then(|x| x.parse::<f64>().ok())
+        {
+            self.destruction = Some(destruction);
+        }
+    }
+}
+
+/// Publishes `PROGRESS` statuses of one game. Phase changes go out right away, simulator
+/// progress at most once per `PROGRESS_INTERVAL` and only when it changed.
+pub struct ProgressReporter<'a> {
+    game_id: String,
+    no_of_turns: u32,
+    /// `None` when progress updates are disabled
+    interval: Option<Duration>,
+    publish: &'a dyn Fn(GameStatus),
+    simulator: Arc<Mutex<SimulatorProgress>>,
+    phase: Option<GamePhase>,
+    last: Option<(Instant, GameProgress)>,
+}
+
+impl<'a> ProgressReporter<'a> {
+    /// An `interval` of 0 milliseconds disables progress updates
+    pub fn new(game_request: &GameRequest, interval: u64, publish: &'a dyn Fn(GameStatus)) -> Self {
+        ProgressReporter {
+            game_id: game_request.game_id.clone(),
+            no_of_turns: game_request.parameters.no_of_turns,
+            interval: (interval > 0).then(|| Duration::from_millis(interval)),
+            publish,
+            simulator: Arc::new(Mutex::new(SimulatorProgress::default())),
+            phase: None,
+            last: None,
+        }
+    }
+
+    /// Handed to the simulator's `ProcessOutput`
+    pub fn simulator(&self) -> Arc<Mutex<SimulatorProgress>> {
+        Arc::clone(&self.simulator)
+    }
+
+    pub fn phase(&mut self, phase: GamePhase) {
+        self.phase = Some(phase);
+        self.send(true);
+    }
+
+    /// Called after every round of the epoll loop
+    pub fn tick(&mut self) {
+        self.send(false);
+    }
+
+    fn send(&mut self, force: bool) {
+        let (interval, phase) = match (self.interval, self.phase) {
+            (Some(interval), Some(phase)) => (interval, phase),
+            _ => return,
+        };
+        let progress = {
+            let simulator = self.simulator.lock().unwrap();
+            GameProgress {
+                phase,
+                turn: simulator.turn,
+                no_of_turns: self.no_of_turns,
+                destruction: simulator.destruction,
+            }
+        };
+        if let Some((at, last)) = &self.last {
+            if !force && (at.elapsed() < interval || *last == progress) {
+                return;
+            }
+        }
+        (self.publish)(create_progress_response(&self.game_id, progress.clone()));
+        self.last = Some((Instant::now(), progress));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, thread, time::Duration};
+
+    use super::{ProgressReporter, SimulatorProgress};
+    use crate::{
+        request::tests::example_request,
+        response::{GamePhase, GameStatus, GameStatusEnum},
+    };
+
+    #[test]
+    fn lines_split_across_chunks() {
+        let mut progress = SimulatorProgress::default();
+        progress.feed(b"TURN, 1\nCOINS, 10\nTU");
+        assert_eq!(progress.turn, Some(1));
+        progress.feed(b"RN, 12\nDESTRUCTION, 4");
+        assert_eq!(progress.turn, Some(12));
+        assert_eq!(progress.destruction, None);
+        progress.feed(b"2.5%\n");
+        assert_eq!(progress.destruction, Some(42.5));
+
+        // An overlong line is skipped whole, including the part after the limit
+        progress.feed(&[b'x'; 300]);
+        progress.feed(b"TURN, 99\nTURN, 13\n");
+        assert_eq!(progress.turn, Some(13));
+    }
+
+    #[test]
+    fn rate_limits_simulator_progress() {
+        let request = example_request();
+        let sent = RefCell::new(vec![]);
+        let publish = |status: GameStatus| sent.borrow_mut().push(status);
+        let mut reporter = ProgressReporter::new(&request, 50, &publish);
+
+        reporter.phase(GamePhase::COMPILING);
+        reporter.phase(GamePhase::RUNNING);
+        reporter.simulator().lock().unwrap().feed(b"TURN, 1\n");
+        // Too soon after the phase change
+        reporter.tick();
+        thread::sleep(Duration::from_millis(60));
+        reporter.tick();
+        // Nothing changed since
+        thread::sleep(Duration::from_millis(60));
+        reporter.tick();
+
+        let sent = sent.into_inner();
+        assert!(sent
+            .iter()
+            .all(|status| status.game_status == GameStatusEnum::PROGRESS));
+        let progress = sent
+            .into_iter()
+            .map(|status| status.progress.unwrap())
+            .map(|progress| (progress.phase, progress.turn))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            progress,
+            vec![
+                (GamePhase::COMPILING, None),
+                (GamePhase::RUNNING, None),
+                (GamePhase::RUNNING, Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn disabled_with_zero_interval() {
+        let request = example_request();
+        let sent = RefCell::new(0);
+        let publish = |_: GameStatus| *sent.borrow_mut() += 1;
+        let mut reporter = ProgressReporter::new(&request, 0, &publish);
+        reporter.phase(GamePhase::RUNNING);
+        reporter.tick();
+        assert_eq!(sent.into_inner(), 0);
+    }
+}
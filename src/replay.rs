@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::response::{PlayerPrint, Replay, ReplayEvent, ReplayTurn};
+
+/// Turns one simulator line into a replay event. `fields` is the line split at `,` and trimmed,
+/// the first field being the kind (e.g. `["SPAWN", "1", "2", "3"]`).
+pub trait LineGrammar: Send + Sync {
+    /// `None` passes the line on to the next grammar
+    fn parse(&self, fields: &[&str]) -> Option<ReplayEvent>;
+}
+
+/// Fallback for lines no other grammar claims: the fields after the kind as strings
+pub struct FieldsGrammar;
+
+impl LineGrammar for FieldsGrammar {
+    fn parse(&self, fields: &[&str]) -> Option<ReplayEvent> {
+        let (kind, values) = fields.split_first()?;
+        Some(ReplayEvent {
+            kind: kind.to_string(),
+            data: Value::from(values.to_vec()),
+        })
+    }
+}
+
+/// Turnwise player logs (see `get_turnwise_logs`), tagged in player-vs-player matches
+pub type TurnwisePrints<'a> = (Option<&'a str>, &'a HashMap<usize, Vec<String>>);
+
+/// Parses the simulator log into a `Replay`. `TURN`, `COINS` and `DESTRUCTION` are understood
+/// natively, every other line goes through the registered grammars in order.
+#[derive(Default)]
+pub struct ReplayParser {
+    grammars: Vec<Box<dyn LineGrammar>>,
+}
+
+impl ReplayParser {
+    /// Grammars are tried in the order they were registered, `FieldsGrammar` last
+    pub fn register(mut self, grammar: impl LineGrammar + 'static) -> Self {
+        self.grammars.push(Box::new(grammar));
+        self
+    }
+
+    pub fn parse(&self, simulator_log: &str, prints: &[TurnwisePrints]) -> Replay {
+        let mut replay = Replay::default();
+        for ln in simulator_log.lines() {
+            let fields = ln.split(',').map(str::trim).collect::<Vec<_>>();
+            if fields.iter().all(|field| field.is_empty()) {
+                continue;
+            }
+            match fields.as_slice() {
+                ["TURN", num] if num.parse::<usize>().is_ok() => {
+                    let turn = num.parse().unwrap();
+                    replay.turns.push(ReplayTurn {
+                        turn,
+                        prints: turn_prints(turn, prints),
+                        ..ReplayTurn::default()
+                    });
+                    continue;
+                }
+                ["COINS", coins] => {
+                    if let (Some(turn), Ok(coins)) = (replay.turns.last_mut(), coins.parse()) {
+                        turn.coins = Some(coins);
+                        continue;
+                    }
+                }
+                ["DESTRUCTION", percentage] => {
+                    let percentage = percentage.strip_suffix('%').and_then(|x| x.parse().ok());
+                    if let (Some(turn), Some(percentage)) = (replay.turns.last_mut(), percentage) {
+                        turn.destruction = Some(percentage);
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+
+            let event = self
+                .grammars
+                .iter()
+                .find_map(|grammar| grammar.parse(&fields))
+                .or_else(|| FieldsGrammar.parse(&fields));
+            if let Some(event) = event {
+                match replay.turns.last_mut() {
+                    Some(turn) => turn.events.push(event),
+                    None => replay.events.push(event),
+                }
+            }
+        }
+        replay
+    }
+}
+
+fn turn_prints(turn: usize, prints: &[TurnwisePrints]) -> Vec<PlayerPrint> {
+    prints
+        .iter()
+        .flat_map(|(tag, logs)| {
+            logs.get(&turn)
+                .into_iter()
+                .flatten()
+                .map(move |message| PlayerPrint {
+                    tag: tag.map(str::to_owned),
+                    message: message.clone(),
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::{LineGrammar, ReplayParser};
+    use crate::response::{PlayerPrint, ReplayEvent, ReplayTurn};
+
+    struct SpawnGrammar;
+
+    impl LineGrammar for SpawnGrammar {
+        fn parse(&self, fields: &[&str]) -> Option<ReplayEvent> {
+            match fields {
+                ["SPAWN", id, x, y] => Some(ReplayEvent {
+                    kind: "SPAWN".to_owned(),
+                    data: json!({
+                        "id": id.parse::<u32>().ok()?,
+                        "x": x.parse::<u32>().ok()?,
+                        "y": y.parse::<u32>().ok()?,
+                    }),
+                }),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn turns_with_prints_and_events() {
+        let simulator_log = "MAP, 64, 64\n\
+                             TURN, 1\n\
+                             SPAWN, 3, 10, 12\n\
+                             COINS, 100\n\
+                             DESTRUCTION, 20.5%\n\
+                             \n\
+                             TURN, 2\n\
+                             SPAWN, 4, x, 1\n";
+        let attacker = HashMap::from([(1, vec!["spawning".to_owned()])]);
+        let defender = HashMap::from([(2, vec!["holding".to_owned()])]);
+
+        let replay = ReplayParser::default().register(SpawnGrammar).parse(
+            simulator_log,
+            &[(Some("attacker"), &attacker), (Some("defender"), &defender)],
+        );
+
+        assert_eq!(
+            replay.events,
+            vec![ReplayEvent {
+                kind: "MAP".to_owned(),
+                data: json!(["64", "64"]),
+            }]
+        );
+        assert_eq!(
+            replay.turns,
+            vec![
+                ReplayTurn {
+                    turn: 1,
+                    coins: Some(100),
+                    destruction: Some(20.5),
+                    prints: vec![PlayerPrint {
+                        tag: Some("attacker".to_owned()),
+                        message: "spawning".to_owned(),
+                    }],
+                    events: vec![ReplayEvent {
+                        kind: "SPAWN".to_owned(),
+                        data: json!({"id": 3, "x": 10, "y": 12}),
+                    }],
+                },
+                ReplayTurn {
+                    turn: 2,
+                    coins: None,
+                    destruction: None,
+                    prints: vec![PlayerPrint {
+                        tag: Some("defender".to_owned()),
+                        message: "holding".to_owned(),
+                    }],
+                    // Not claimed by the spawn grammar, so only split into fields
+                    events: vec![ReplayEvent {
+                        kind: "SPAWN".to_owned(),
+                        data: json!(["4", "x", "1"]),
+                    }],
+                },
+            ]
+        );
+    }
+}
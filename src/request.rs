@@ -2,6 +2,8 @@ use serde::de;
 use serde::Deserialize;
 use serde::Deserializer;
 
+use crate::compression::LogEncoding;
+
 #[derive(Deserialize, Debug, PartialEq)]
 pub struct Attacker {
     pub id: u32,
@@ -31,13 +33,61 @@ pub struct GameParameters {
     pub no_of_coins: u32,
 }
 
+/// Name of a language in the driver config, e.g. `CPP`, `JAVA` or `PYTHON`
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(transparent)]
+pub struct Language(pub String);
+
+impl Language {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Language {
+    fn from(name: &str) -> Self {
+        Language(name.to_owned())
+    }
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Deserialize, Debug, PartialEq)]
-pub enum Language {
-    CPP,
-    JAVA,
-    PYTHON,
+pub struct PlayerCode {
+    pub source_code: String,
+    pub language: Language,
+}
+
+/// One submission taking part in a game, `tag` tells it apart in logs and results
+#[derive(Debug, PartialEq)]
+pub struct Participant<'a> {
+    pub tag: &'a str,
+    pub source_code: &'a str,
+    pub language: &'a Language,
 }
 
+/// A game against the simulator. When `defender` is set it is a player-vs-player match:
+/// `source_code` / `language` is the attacker and both submissions run at the same time.
+///
+/// The request carries at most these two submissions, but the simulator is told about any
+/// number of participants (see `participants`). Instead of the game parameters, the simulator
+/// of a match first reads from stdin
+///
+/// ```text
+/// PVP <n>
+/// <tag 1>
+/// ...
+/// <tag n>
+/// ```
+///
+/// followed by the usual initial input, and talks to participant `tag` over
+/// `/fifos/{tag}_in` (written by the simulator, the player's stdin) and `/fifos/{tag}_out`
+/// (the player's stdout, read by the simulator). Player logs come back as
+/// `PRINT, {tag}, ...` lines.
 #[derive(Deserialize, Debug, PartialEq)]
 pub struct GameRequest {
     pub game_id: String,
@@ -46,6 +96,40 @@ pub struct GameRequest {
     pub language: Language,
     #[serde(deserialize_with = "deserialize_from_str")]
     pub map: Vec<Vec<u8>>,
+    #[serde(default)]
+    pub defender: Option<PlayerCode>,
+    /// Overrides `LOG_ENCODING` for the log of this game
+    #[serde(default)]
+    pub log_encoding: Option<LogEncoding>,
+}
+
+impl GameRequest {
+    /// Every submission of the game, in the order they are started
+    pub fn participants(&self) -> Vec<Participant<'_>> {
+        match &self.defender {
+            None => vec![Participant {
+                tag: "player",
+                source_code: &self.source_code,
+                language: &self.language,
+            }],
+            Some(defender) => vec![
+                Participant {
+                    tag: "attacker",
+                    source_code: &self.source_code,
+                    language: &self.language,
+                },
+                Participant {
+                    tag: "defender",
+                    source_code: &defender.source_code,
+                    language: &defender.language,
+                },
+            ],
+        }
+    }
+
+    pub fn is_pvp(&self) -> bool {
+        self.defender.is_some()
+    }
 }
 
 // Reference: https://serde.rs/attr-bound.html
@@ -58,9 +142,28 @@ where
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+
+    use super::{Attacker, Defender, GameParameters, GameRequest, Language};
+
+    /// A single player CPP game without troops, for tests to override what they need
+    pub(crate) fn example_request() -> GameRequest {
+        GameRequest {
+            game_id: "1".to_owned(),
+            parameters: GameParameters {
+                attackers: vec![],
+                defenders: vec![],
+                no_of_turns: 500,
+                no_of_coins: 500,
+            },
+            source_code: "".to_owned(),
+            language: Language::from("CPP"),
+            map: vec![vec![]],
+            defender: None,
+            log_encoding: None,
+        }
+    }
 
-    use super::{Attacker, Defender, GameParameters, GameRequest};
     #[test]
     pub fn deserealization_test() {
         // An example request that we might get from backend
@@ -110,12 +213,28 @@ mod tests {
                 no_of_turns: 500,
                 no_of_coins: 1000,
             },
-            language: super::Language::PYTHON,
+            language: Language::from("PYTHON"),
             source_code: r#"print(x)"#.to_owned(),
             map: vec![vec![1, 0], vec![0, 2]],
+            defender: None,
+            log_encoding: None,
         };
         let deserealized_example_request: GameRequest =
             serde_json::from_str(example_request).unwrap();
         assert_eq!(deserealized_example_request, expected_deserealized_struct);
     }
+
+    #[test]
+    pub fn pvp_participants_test() {
+        let example_request = r#"{"game_id":"1","parameters":{"attackers":[],"defenders":[],"no_of_turns":500,"no_of_coins":1000},"source_code":"print(x)","language":"PYTHON","map":"[[0]]","defender":{"source_code":"int main() {}","language":"CPP"}}"#;
+        let request: GameRequest = serde_json::from_str(example_request).unwrap();
+
+        assert!(request.is_pvp());
+        let participants = request.participants();
+        assert_eq!(participants.len(), 2);
+        assert_eq!(participants[0].tag, "attacker");
+        assert_eq!(participants[0].language.name(), "PYTHON");
+        assert_eq!(participants[1].tag, "defender");
+        assert_eq!(participants[1].source_code, "int main() {}");
+    }
 }
@@ -1,5 +1,7 @@
 use serde::Serialize;
 
+use crate::compression::LogEncoding;
+
 #[derive(Serialize, Debug, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum GameStatusEnum {
@@ -7,6 +9,117 @@ pub enum GameStatusEnum {
     EXECUTING,
     EXECUTED,
     EXECUTE_ERROR,
+    /// Sent any number of times between `EXECUTING` and the final status, safe to ignore
+    PROGRESS,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum GamePhase {
+    COMPILING,
+    RUNNING,
+}
+
+/// Where an executing game is at, as far as the simulator's stderr tells
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct GameProgress {
+    pub phase: GamePhase,
+    pub turn: Option<usize>,
+    pub no_of_turns: u32,
+    pub destruction: Option<f64>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum CompileCacheStatus {
+    HIT,
+    MISS,
+}
+
+/// Outcome of one submission in a player-vs-player match
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ParticipantResult {
+    pub tag: String,
+    pub has_errors: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compile_cache: Option<CompileCacheStatus>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum Severity {
+    ERROR,
+    WARNING,
+    NOTE,
+}
+
+/// One compiler message, with the path relative to the player's code so that the
+/// editor can underline it
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: u32,
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+    pub snippet: Option<String>,
+}
+
+/// A `PRINT` of a player during one turn, tagged with the participant in player-vs-player matches
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct PlayerPrint {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub message: String,
+}
+
+/// Any simulator line besides `TURN`, `COINS` and `DESTRUCTION`, e.g. `SPAWN, 1, 2, 3`.
+/// `data` is whatever the matching `replay::LineGrammar` made of the line.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct ReplayEvent {
+    pub kind: String,
+    pub data: serde_json::Value,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone, Default)]
+pub struct ReplayTurn {
+    pub turn: usize,
+    pub coins: Option<u32>,
+    pub destruction: Option<f64>,
+    pub prints: Vec<PlayerPrint>,
+    pub events: Vec<ReplayEvent>,
+}
+
+/// The game log as typed per turn objects, for the replay viewer
+#[derive(Serialize, Debug, PartialEq, Clone, Default)]
+pub struct Replay {
+    /// Events the simulator emitted before the first turn
+    pub events: Vec<ReplayEvent>,
+    pub turns: Vec<ReplayTurn>,
+}
+
+/// Limits a process ran under, as configured for its phase
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct ProcessLimits {
+    pub wall_time_ms: Option<u64>,
+    pub cpu_time_ms: u64,
+    pub memory_bytes: Option<u64>,
+}
+
+/// What one compiler, runner or simulator process used, next to its limits
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct ProcessMetrics {
+    /// e.g. `cpp_compiler`, `python_runner` or `simulator`
+    pub role: String,
+    /// The participant in player-vs-player matches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub wall_time_ms: u64,
+    /// `None` when the process ran in a docker or podman container without a
+    /// `CONTAINER_CGROUP_PARENT`, or its cgroup's `cpu.stat` could not be read
+    pub cpu_time_ms: Option<u64>,
+    pub peak_memory_bytes: Option<u64>,
+    pub limits: ProcessLimits,
 }
 
 #[derive(Serialize, Debug, PartialEq)]
@@ -15,6 +128,23 @@ pub struct GameResult {
     pub coins_used: u64,
     pub has_errors: bool,
     pub log: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compile_cache: Option<CompileCacheStatus>,
+    /// Only filled for player-vs-player matches
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub participants: Vec<ParticipantResult>,
+    /// Parsed compiler output, only filled for compilation errors
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+    /// Only filled for finished games, `log` stays the source of truth for older clients
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replay: Option<Replay>,
+    /// Set when `log` is compressed and base64 encoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_encoding: Option<LogEncoding>,
+    /// Every compiler, runner and simulator process that finished, in the order they did
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub metrics: Vec<ProcessMetrics>,
 }
 
 #[derive(Serialize, Debug, PartialEq)]
@@ -22,6 +152,9 @@ pub struct GameStatus {
     pub game_id: String,
     pub game_status: GameStatusEnum,
     pub game_result: Option<GameResult>,
+    /// Only set on `PROGRESS` messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<GameProgress>,
 }
 
 #[cfg(test)]
@@ -37,6 +170,7 @@ mod tests {
             game_id: "030af985-f4b5-4914-94d8-e559576449e3".to_string(),
             game_status: GameStatusEnum::EXECUTING,
             game_result: None,
+            progress: None,
         };
 
         let serialized_game_status = serde_json::to_string(&game_status).unwrap();
@@ -1,12 +1,434 @@
-use std::{fs::File, process::Child};
+use std::{
+    convert::TryFrom,
+    fs::File,
+    path::Path,
+    process::{Child, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
 
-use crate::error::SimulatorError;
+use log::warn;
+use nix::{libc, sys::signal::Signal};
 
-pub mod cpp;
-pub mod java;
-pub mod py;
+use crate::{
+    cache::CompileCache,
+    config::DriverConfig,
+    diagnostics,
+    error::SimulatorError,
+    metrics::{self, GameMetrics, Usage},
+    response::{CompileCacheStatus, ProcessMetrics},
+    sandbox::{ContainerSpec, SandboxBackend},
+    shutdown::Shutdown,
+};
+
+pub mod player;
 pub mod simulator;
 
+#[derive(Debug, Default, PartialEq)]
+pub struct CompileInfo {
+    /// `None` when there is no compile step or the cache is disabled
+    pub cache: Option<CompileCacheStatus>,
+}
+
 pub trait Runnable {
+    /// Prepares the player's code before `run`, nothing to do for interpreted languages.
+    /// A compiler that ran is recorded in `metrics`, whether it succeeded or not, and killed
+    /// if it still runs at `deadline` or the driver shuts down.
+    fn compile(
+        &self,
+        _metrics: &GameMetrics,
+        _deadline: Instant,
+        _shutdown: &Shutdown,
+    ) -> Result<CompileInfo, SimulatorError> {
+        Ok(CompileInfo::default())
+    }
     fn run(&self, stdin: File, stdout: File) -> Result<Child, SimulatorError>;
+    /// What the process started by `run` used, given what `Process::wait` measured
+    fn measure(&self, usage: Usage) -> ProcessMetrics;
+    /// Whether the process started by `run` was killed for going over its memory limit
+    fn out_of_memory(&self) -> bool;
+    /// The signal that killed the process started by `run`, if any
+    fn exit_signal(&self, status: ExitStatus) -> Option<i32>;
+    /// `WALL_TIME_LIMIT` of the run phase in seconds, if any
+    fn wall_time_limit(&self) -> Option<u64>;
+}
+
+/// Why a process failed, from its exit status, the signal that killed it as read by its
+/// backend, what it used and whether the backend saw it run out of memory
+pub fn exit_error(
+    status: ExitStatus,
+    signal: Option<i32>,
+    metrics: &ProcessMetrics,
+    out_of_memory: bool,
+) -> SimulatorError {
+    let limits = &metrics.limits;
+    if out_of_memory {
+        let limit = limits
+            .memory_bytes
+            .map(|bytes| format!(" of {} MB", bytes >> 20))
+            .unwrap_or_default();
+        return SimulatorError::MemoryLimitExceeded(format!(
+            "Program used more than the memory limit{limit}, so it was killed"
+        ));
+    }
+    let out_of_cpu_time = metrics
+        .cpu_time_ms
+        .is_some_and(|used| used >= limits.cpu_time_ms);
+    match signal {
+        Some(libc::SIGXCPU) => cpu_time_exceeded(limits.cpu_time_ms),
+        Some(libc::SIGKILL) if out_of_cpu_time => cpu_time_exceeded(limits.cpu_time_ms),
+        // Without the CPU time (docker and podman without `CONTAINER_CGROUP_PARENT`) a kill
+        // is most likely one of the limits
+        Some(libc::SIGKILL) if metrics.cpu_time_ms.is_none() => SimulatorError::TimeOutError(
+            "Process took longer than the specified time to execute, so it was killed".to_owned(),
+        ),
+        Some(signal) => SimulatorError::RuntimeError(format!(
+            "Program was killed by signal {signal} ({})",
+            Signal::try_from(signal).map_or("unknown", Signal::as_str)
+        )),
+        None => SimulatorError::RuntimeError(format!(
+            "Program exited with non zero exit code: {}",
+            status.code().unwrap_or_default()
+        )),
+    }
+}
+
+fn cpu_time_exceeded(limit_ms: u64) -> SimulatorError {
+    SimulatorError::CpuTimeLimitExceeded(format!(
+        "Program used more than the CPU time limit of {} seconds, so it was killed",
+        limit_ms / 1000
+    ))
+}
+
+/// Runs the compile container for `spec`, unless `artifact` (relative to the game
+/// directory) can be taken from the compile cache. The compiler's metrics are recorded
+/// under `tag` in `game_metrics`. The compiler is stopped at `deadline`, or earlier when its
+/// phase has a wall time limit or `shutdown` aborts the game. Cache problems never fail a game.
+/// Without an artifact (a syntax check only) the cache is never used.
+#[allow(clippy::too_many_arguments)]
+pub fn compile(
+    sandbox: &dyn SandboxBackend,
+    spec: &ContainerSpec,
+    config: &DriverConfig,
+    game_dir: &str,
+    artifact: Option<&str>,
+    game_metrics: &GameMetrics,
+    tag: Option<&str>,
+    deadline: Instant,
+    shutdown: &Shutdown,
+) -> Result<CompileInfo, SimulatorError> {
+    let artifact = artifact.map(|artifact| Path::new(game_dir).join(artifact));
+
+    let cache = artifact
+        .as_ref()
+        .and_then(|_| CompileCache::from_config(config));
+    let cached = cache.and_then(|cache| {
+        let key = sandbox
+            .fingerprint(spec)
+            .map_err(|e| format!("{e:?}"))
+            .and_then(|fingerprint| {
+                CompileCache::key(
+                    Path::new(game_dir),
+                    &[
+                        &fingerprint,
+                        &format!("{:?}", spec.command),
+                        &format!("{:?}", spec.limits),
+                    ],
+                )
+                .map_err(|e| e.to_string())
+            });
+        match key {
+            Ok(key) => Some((cache, key)),
+            Err(e) => {
+                warn!("Compile cache unavailable for {}: {e}", spec.name);
+                None
+            }
+        }
+    });
+
+    if let (Some((cache, key)), Some(artifact)) = (&cached, &artifact) {
+        match cache.fetch(key, artifact) {
+            Ok(true) => {
+                return Ok(CompileInfo {
+                    cache: Some(CompileCacheStatus::HIT),
+                })
+            }
+            Ok(false) => {}
+            Err(e) => warn!("Compile cache lookup failed for {}: {e}", spec.name),
+        }
+    }
+
+    // Some compilers (e.g. tsc) print their diagnostics to stdout
+    let started = Instant::now();
+    let compile = sandbox.spawn(spec, Stdio::null(), Stdio::piped())?;
+
+    let deadline = spec.limits.wall_time_limit.map_or(deadline, |limit| {
+        deadline.min(started + Duration::from_secs(limit))
+    });
+    let (out, usage) =
+        metrics::wait_with_output(compile, started, deadline, Some(shutdown.abort_fd())).map_err(
+            |err| {
+                SimulatorError::UnidentifiedError(format!(
+                    "Unable to wait for compilation to finish, {err}"
+                ))
+            },
+        )?;
+    let compile_metrics =
+        metrics::measure(tag.map(str::to_owned), spec, sandbox.usage(spec, usage));
+    let out = match out {
+        Some(out) => out,
+        None if shutdown.is_aborted() => {
+            game_metrics.record(compile_metrics);
+            return Err(SimulatorError::ShutdownError(
+                "The driver shut down during compilation".to_owned(),
+            ));
+        }
+        None => {
+            game_metrics.record(compile_metrics);
+            return Err(SimulatorError::TimeOutError(format!(
+                "Compilation did not finish within {} seconds, so it was stopped",
+                deadline
+                    .saturating_duration_since(started)
+                    .as_secs_f64()
+                    .round()
+            )));
+        }
+    };
+    let signal = sandbox.exit_signal(out.status);
+    let out_of_memory = !out.status.success() && sandbox.out_of_memory(spec);
+    let error = (!out.status.success())
+        .then(|| exit_error(out.status, signal, &compile_metrics, out_of_memory));
+    game_metrics.record(compile_metrics);
+
+    if let Some(error) = error {
+        // A compiler rejecting the code just exits with an error, anything else is reported
+        // like it would be for the player's program
+        if signal.is_some() || out_of_memory {
+            return Err(error);
+        }
+        let stdout = String::from_utf8_lossy(&out.stdout).into_owned();
+        let stderr = String::from_utf8_lossy(&out.stderr).into_owned();
+        return Err(SimulatorError::CompilationError(
+            diagnostics::relative_paths(&(stdout + &stderr), &code_dirs(spec, game_dir)),
+        ));
+    }
+
+    if let (Some((cache, key)), Some(artifact)) = (&cached, &artifact) {
+        if let Err(e) = cache.store(key, artifact) {
+            warn!("Unable to store {} in the compile cache: {e}", spec.name);
+        }
+    }
+
+    Ok(CompileInfo {
+        cache: cached.map(|_| CompileCacheStatus::MISS),
+    })
+}
+
+/// Where the compiler may have seen the player's code: the directory mounts of the
+/// container and, for the native backend, the game directory itself
+fn code_dirs(spec: &ContainerSpec, game_dir: &str) -> Vec<String> {
+    let with_slash = |dir: &str| format!("{}/", dir.trim_end_matches('/'));
+    spec.mounts
+        .iter()
+        .filter(|mount| mount.source.ends_with('/'))
+        .map(|mount| with_slash(&mount.target))
+        .chain(std::iter::once(with_slash(game_dir)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fs,
+        os::unix::process::ExitStatusExt,
+        process::ExitStatus,
+        time::{Duration, Instant},
+    };
+
+    use nix::libc;
+
+    use super::{compile, exit_error, CompileInfo};
+    use crate::{
+        config::{
+            tests::{example_config, example_env},
+            DriverConfig,
+        },
+        diagnostics,
+        error::SimulatorError,
+        metrics::GameMetrics,
+        response::{ProcessLimits, ProcessMetrics},
+        sandbox::{cli::Cli, native::Native, ContainerSpec, SandboxBackend},
+        shutdown::Shutdown,
+        utils::{example_spec, TestDir},
+    };
+
+    fn metrics(cpu_time_ms: Option<u64>) -> ProcessMetrics {
+        ProcessMetrics {
+            role: "cpp_runner".to_owned(),
+            tag: None,
+            wall_time_ms: 2000,
+            cpu_time_ms,
+            peak_memory_bytes: None,
+            limits: ProcessLimits {
+                wall_time_ms: None,
+                cpu_time_ms: 1000,
+                memory_bytes: Some(100 << 20),
+            },
+        }
+    }
+
+    #[test]
+    fn kill_reasons() {
+        let killed = ExitStatus::from_raw(libc::SIGKILL);
+        // Through a container engine: 128 + SIGKILL
+        let engine_killed = ExitStatus::from_raw(137 << 8);
+        let error = |status: ExitStatus, cpu_time_ms, out_of_memory| {
+            let cli = Cli::new("docker", vec![], None, None);
+            exit_error(
+                status,
+                cli.exit_signal(status),
+                &metrics(cpu_time_ms),
+                out_of_memory,
+            )
+        };
+
+        assert!(matches!(
+            error(engine_killed, None, true),
+            SimulatorError::MemoryLimitExceeded(e) if e.contains("of 100 MB")
+        ));
+        assert!(matches!(
+            error(ExitStatus::from_raw(libc::SIGXCPU), Some(1000), false),
+            SimulatorError::CpuTimeLimitExceeded(_)
+        ));
+        assert!(matches!(
+            error(ExitStatus::from_raw(152 << 8), None, false),
+            SimulatorError::CpuTimeLimitExceeded(_)
+        ));
+        // The hard limit after an ignored SIGXCPU
+        assert!(matches!(
+            error(killed, Some(2000), false),
+            SimulatorError::CpuTimeLimitExceeded(_)
+        ));
+        // Nothing known about the CPU time, like the engines used to report it
+        assert!(matches!(
+            error(engine_killed, None, false),
+            SimulatorError::TimeOutError(_)
+        ));
+        assert!(matches!(
+            error(engine_killed, Some(10), false),
+            SimulatorError::RuntimeError(e) if e == "Program was killed by signal 9 (SIGKILL)"
+        ));
+        assert!(matches!(
+            error(ExitStatus::from_raw(libc::SIGSEGV), Some(10), false),
+            SimulatorError::RuntimeError(e) if e.contains("SIGSEGV")
+        ));
+        assert!(matches!(
+            error(ExitStatus::from_raw(1 << 8), Some(10), false),
+            SimulatorError::RuntimeError(e) if e == "Program exited with non zero exit code: 1"
+        ));
+        // Only the container engines report a signal as an exit code
+        let native = Native::new(HashMap::new());
+        assert!(matches!(
+            exit_error(
+                engine_killed,
+                native.exit_signal(engine_killed),
+                &metrics(Some(10)),
+                false
+            ),
+            SimulatorError::RuntimeError(e) if e == "Program exited with non zero exit code: 137"
+        ));
+    }
+
+    /// Compiles in `dir` with the native backend running `script`
+    fn compile_natively(script: &str, dir: &str) -> Result<CompileInfo, SimulatorError> {
+        let native = Native::new(HashMap::from([(
+            "cpp_compiler".to_owned(),
+            script.to_owned(),
+        )]));
+        let mut spec = ContainerSpec {
+            interactive: false,
+            current_dir: Some(dir.to_owned()),
+            ..example_spec("cpp_compiler")
+        };
+        spec.limits.cpu_time_limit = 30;
+        compile(
+            &native,
+            &spec,
+            &example_config(),
+            dir,
+            None,
+            &GameMetrics::default(),
+            None,
+            Instant::now() + Duration::from_secs(60),
+            &Shutdown::new().unwrap(),
+        )
+    }
+
+    #[test]
+    fn only_a_failing_compiler_is_a_compilation_error() {
+        let compile_with = |script: &str| compile_natively(script, "/tmp");
+
+        assert!(matches!(
+            compile_with("echo 'run.cpp:1:1: error: expected declaration' >&2; exit 1"),
+            Err(SimulatorError::CompilationError(e)) if e.contains("expected declaration")
+        ));
+        // A plain exit code, whatever its value
+        assert!(matches!(
+            compile_with("exit 137"),
+            Err(SimulatorError::CompilationError(_))
+        ));
+        assert!(matches!(
+            compile_with("echo partial >&2; kill -9 $$"),
+            Err(SimulatorError::RuntimeError(e)) if e == "Program was killed by signal 9 (SIGKILL)"
+        ));
+        assert!(matches!(
+            compile_with("kill -SEGV $$"),
+            Err(SimulatorError::RuntimeError(e)) if e.contains("SIGSEGV")
+        ));
+    }
+
+    #[test]
+    fn rustc_diagnostics_are_a_compilation_error() {
+        let mut env = example_env();
+        env.insert("LANGUAGES".to_owned(), "RUST".to_owned());
+        env.insert("RUST_COMPILER_IMAGE".to_owned(), "rust-compiler".to_owned());
+        env.insert("RUST_RUNNER_IMAGE".to_owned(), "rust-runner".to_owned());
+        let dir = TestDir::new("runner_rustc_diagnostics_are_a_compilation_error");
+        env.insert(
+            "RUST_BOILERPLATE_DIR".to_owned(),
+            dir.to_str().unwrap().to_owned(),
+        );
+        let config = DriverConfig::from_sources(|k| env.get(k).cloned(), None).unwrap();
+
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"run\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("src/main.rs"),
+            "mod run;\n\nfn main() {\n    run::run();\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("src/run.rs"),
+            "pub fn run() {\n    let _turns: u32 = \"ten\";\n}\n",
+        )
+        .unwrap();
+
+        // The builtin command, run where the compiler image would have mounted the code
+        let dir = dir.to_str().unwrap();
+        let rust = config.language("RUST").unwrap();
+        let script = rust.compile.as_ref().unwrap().phase.command.join(" ");
+        let result = compile_natively(&script.replace("/player_code", dir), dir);
+
+        let Err(SimulatorError::CompilationError(output)) = result else {
+            panic!("expected a compilation error, got {:?}", result);
+        };
+        let diagnostics = diagnostics::parse(&output);
+        assert_eq!(diagnostics[0].file, "src/run.rs");
+        assert_eq!(diagnostics[0].line, 2);
+        assert!(diagnostics[0].message.contains("mismatched types"));
+    }
 }
@@ -0,0 +1,169 @@
+use std::{
+    fs::File,
+    process::{Child, ExitStatus},
+    sync::Arc,
+    time::Instant,
+};
+
+use crate::{
+    config::{DriverConfig, LanguageConfig, PhaseConfig},
+    error::SimulatorError,
+    metrics::{self, GameMetrics, Usage},
+    response::ProcessMetrics,
+    sandbox::{ContainerSpec, Mount, SandboxBackend},
+    shutdown::Shutdown,
+};
+
+use super::{compile, CompileInfo, Runnable};
+
+/// Compiles and runs a submission as described by its language config
+pub struct Runner {
+    current_dir: String,
+    game_id: String,
+    /// The participant in player-vs-player matches, named in its metrics
+    tag: Option<String>,
+    language: LanguageConfig,
+    config: Arc<DriverConfig>,
+    sandbox: Arc<dyn SandboxBackend>,
+}
+
+impl Runner {
+    pub fn new(
+        current_dir: String,
+        game_id: String,
+        tag: Option<String>,
+        language: LanguageConfig,
+        config: Arc<DriverConfig>,
+        sandbox: Arc<dyn SandboxBackend>,
+    ) -> Self {
+        Runner {
+            current_dir,
+            game_id,
+            tag,
+            language,
+            config,
+            sandbox,
+        }
+    }
+
+    fn spec(&self, role: String, phase: &PhaseConfig, interactive: bool) -> ContainerSpec {
+        ContainerSpec {
+            name: format!("{}_{role}", self.game_id),
+            role,
+            image: phase.image.clone(),
+            mounts: phase
+                .mounts
+                .iter()
+                .map(|mount| {
+                    Mount::new(
+                        mount.source.replace("{dir}", &self.current_dir),
+                        &mount.target,
+                    )
+                })
+                .collect(),
+            limits: phase.limits.clone(),
+            command: phase.command.clone(),
+            interactive,
+            current_dir: Some(self.current_dir.clone()),
+        }
+    }
+}
+
+impl Runnable for Runner {
+    fn compile(
+        &self,
+        metrics: &GameMetrics,
+        deadline: Instant,
+        shutdown: &Shutdown,
+    ) -> Result<CompileInfo, SimulatorError> {
+        match &self.language.compile {
+            Some(compile_config) => compile(
+                &*self.sandbox,
+                &self.spec(self.language.compiler_role(), &compile_config.phase, false),
+                &self.config,
+                &self.current_dir,
+                compile_config.artifact.as_deref(),
+                metrics,
+                self.tag.as_deref(),
+                deadline,
+                shutdown,
+            ),
+            None => Ok(CompileInfo::default()),
+        }
+    }
+
+    fn run(&self, stdin: File, stdout: File) -> Result<Child, SimulatorError> {
+        self.sandbox.spawn(
+            &self.spec(self.language.runner_role(), &self.language.run, true),
+            stdin.into(),
+            stdout.into(),
+        )
+    }
+
+    fn measure(&self, usage: Usage) -> ProcessMetrics {
+        let spec = self.spec(self.language.runner_role(), &self.language.run, true);
+        metrics::measure(self.tag.clone(), &spec, self.sandbox.usage(&spec, usage))
+    }
+
+    fn out_of_memory(&self) -> bool {
+        self.sandbox.out_of_memory(&self.spec(
+            self.language.runner_role(),
+            &self.language.run,
+            true,
+        ))
+    }
+
+    fn exit_signal(&self, status: ExitStatus) -> Option<i32> {
+        self.sandbox.exit_signal(status)
+    }
+
+    fn wall_time_limit(&self) -> Option<u64> {
+        self.language.run.limits.wall_time_limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::Runner;
+    use crate::{
+        config,
+        sandbox::{self, Mount},
+    };
+
+    #[test]
+    fn mounts_point_into_the_participant_directory() {
+        let config = config::tests::example_config();
+        let java = config.language("JAVA").unwrap().clone();
+        let config = Arc::new(config);
+        let runner = Runner::new(
+            "/tmp/1/attacker".to_owned(),
+            "1_attacker".to_owned(),
+            Some("attacker".to_owned()),
+            java.clone(),
+            Arc::clone(&config),
+            sandbox::from_config(&config, None),
+        );
+
+        let compile = runner.spec(
+            java.compiler_role(),
+            &java.compile.as_ref().unwrap().phase,
+            false,
+        );
+        assert_eq!(compile.name, "1_attacker_java_compiler");
+        assert_eq!(compile.image, "java-compiler");
+        assert_eq!(
+            compile.mounts,
+            vec![Mount::new("/tmp/1/attacker/".to_owned(), "/player_code/")]
+        );
+
+        let run = runner.spec(java.runner_role(), &java.run, true);
+        assert_eq!(run.name, "1_attacker_java_runner");
+        assert_eq!(
+            run.mounts,
+            vec![Mount::new("/tmp/1/attacker/run.jar".to_owned(), "/run.jar")]
+        );
+        assert_eq!(run.current_dir.as_deref(), Some("/tmp/1/attacker"));
+    }
+}
@@ -1,55 +1,92 @@
 use std::fs::File;
 
-use std::env;
-use std::os::linux::process::CommandExt;
-use std::process::{Command, Stdio};
+use std::process::{Child, ExitStatus};
+use std::sync::Arc;
 
+use crate::config::DriverConfig;
 use crate::error::SimulatorError;
+use crate::metrics::{self, Usage};
+use crate::response::ProcessMetrics;
+use crate::sandbox::{ContainerSpec, Mount, SandboxBackend, FIFO_MOUNT};
 
 use super::Runnable;
 
 pub struct Simulator {
     game_id: String,
+    /// Directory with the participant FIFOs of a player-vs-player match
+    fifo_dir: Option<String>,
+    config: Arc<DriverConfig>,
+    sandbox: Arc<dyn SandboxBackend>,
 }
 
 impl Simulator {
-    pub fn new(game_id: String) -> Self {
-        Simulator { game_id }
+    pub fn new(
+        game_id: String,
+        config: Arc<DriverConfig>,
+        sandbox: Arc<dyn SandboxBackend>,
+    ) -> Self {
+        Simulator {
+            game_id,
+            fifo_dir: None,
+            config,
+            sandbox,
+        }
+    }
+
+    /// A simulator talking to every participant over the FIFOs in `fifo_dir`, which is
+    /// mounted at `/fifos/` (and is the working directory for the native backend)
+    pub fn new_pvp(
+        game_id: String,
+        fifo_dir: String,
+        config: Arc<DriverConfig>,
+        sandbox: Arc<dyn SandboxBackend>,
+    ) -> Self {
+        Simulator {
+            game_id,
+            fifo_dir: Some(fifo_dir),
+            config,
+            sandbox,
+        }
+    }
+
+    fn spec(&self) -> ContainerSpec {
+        ContainerSpec {
+            name: format!("{}_simulator", self.game_id),
+            role: "simulator".to_owned(),
+            image: self.config.simulator.image.clone(),
+            mounts: self
+                .fifo_dir
+                .iter()
+                .map(|dir| Mount::new(format!("{dir}/"), FIFO_MOUNT))
+                .collect(),
+            limits: self.config.simulator.limits.clone(),
+            command: self.config.simulator.command.clone(),
+            interactive: true,
+            current_dir: self.fifo_dir.clone(),
+        }
     }
 }
 
 impl Runnable for Simulator {
-    fn run(&self, stdin: File, stdout: File) -> Result<std::process::Child, SimulatorError> {
-        Command::new("docker")
-            .args([
-                "run",
-                &format!("--memory={}", env::var("RUNTIME_MEMORY_LIMIT").unwrap()),
-                &format!(
-                    "--memory-swap={}",
-                    env::var("RUNTIME_MEMORY_LIMIT").unwrap()
-                ),
-                "--cpus=1",
-                "--ulimit",
-                &format!(
-                    "cpu={}:{}",
-                    env::var("RUNTIME_TIME_LIMIT").unwrap(),
-                    env::var("RUNTIME_TIME_LIMIT").unwrap()
-                ),
-                "--rm",
-                "--name",
-                &format!("{}_simulator", self.game_id),
-                "-i",
-                &env::var("SIMULATOR_IMAGE").unwrap(),
-            ])
-            .create_pidfd(true)
-            .stdin(stdin)
-            .stdout(stdout)
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|err| {
-                SimulatorError::UnidentifiedError(format!(
-                    "Couldnt spawn the simulator process: {err}"
-                ))
-            })
+    fn run(&self, stdin: File, stdout: File) -> Result<Child, SimulatorError> {
+        self.sandbox
+            .spawn(&self.spec(), stdin.into(), stdout.into())
+    }
+
+    fn measure(&self, usage: Usage) -> ProcessMetrics {
+        let spec = self.spec();
+        metrics::measure(None, &spec, self.sandbox.usage(&spec, usage))
+    }
+
+    fn out_of_memory(&self) -> bool {
+        self.sandbox.out_of_memory(&self.spec())
+    }
+
+    fn exit_signal(&self, status: ExitStatus) -> Option<i32> {
+        self.sandbox.exit_signal(status)
+    }
+
+    fn wall_time_limit(&self) -> Option<u64> {
+        self.config.simulator.limits.wall_time_limit
     }
 }
@@ -0,0 +1,92 @@
+use std::{
+    os::unix::process::ExitStatusExt,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::Arc,
+};
+
+use crate::{error::SimulatorError, metrics::Usage};
+
+use super::{
+    image_digest, lifecycle::Containers, oom_killed, pool::WarmPool, run_args, spawn_command,
+    ContainerSpec, SandboxBackend,
+};
+
+/// Runs every phase through a docker compatible CLI: `docker`, or rootless `podman` with
+/// `--userns=keep-id`, which maps the invoking user to the same uid inside the container so
+/// that compilers can write their artifacts back into the game directory.
+pub struct Cli {
+    cli: &'static str,
+    /// Extra `run` arguments of the CLI, see `SandboxKind::cli`
+    extra_args: Vec<String>,
+    pool: Option<Arc<WarmPool>>,
+    /// Every container started, removed when the game is over
+    containers: Containers,
+}
+
+impl Cli {
+    pub fn new(
+        cli: &'static str,
+        extra_args: Vec<String>,
+        pool: Option<Arc<WarmPool>>,
+        cgroup_parent: Option<String>,
+    ) -> Self {
+        Cli {
+            cli,
+            extra_args,
+            pool,
+            containers: Containers::new(cli, cgroup_parent),
+        }
+    }
+}
+
+impl SandboxBackend for Cli {
+    fn spawn(
+        &self,
+        spec: &ContainerSpec,
+        stdin: Stdio,
+        stdout: Stdio,
+    ) -> Result<Child, SimulatorError> {
+        self.containers.track(&spec.name);
+        let mut command = Command::new(self.cli);
+        if let Some(pool) = &self.pool {
+            if let Some(created_as) = pool.checkout(spec) {
+                self.containers.track_pooled(&spec.name, &created_as);
+                command.args(WarmPool::start_args(spec));
+                return spawn_command(command, spec, stdin, stdout);
+            }
+        }
+        command
+            .args(run_args(spec))
+            .args(self.containers.cgroup_args(&spec.name))
+            .args(&self.extra_args)
+            .arg(&spec.image)
+            .args(&spec.command);
+        spawn_command(command, spec, stdin, stdout)
+    }
+
+    fn fingerprint(&self, spec: &ContainerSpec) -> Result<String, SimulatorError> {
+        image_digest(self.cli, &spec.image)
+    }
+
+    fn out_of_memory(&self, spec: &ContainerSpec) -> bool {
+        oom_killed(self.cli, &spec.name)
+    }
+
+    /// The client exits with `128 + signal` when the container was killed
+    fn exit_signal(&self, status: ExitStatus) -> Option<i32> {
+        status.signal().or_else(|| {
+            status
+                .code()
+                .filter(|code| *code > 128)
+                .map(|code| code - 128)
+        })
+    }
+
+    fn usage(&self, spec: &ContainerSpec, usage: Usage) -> Usage {
+        self.containers.usage(&spec.name, usage)
+    }
+
+    fn cleanup(&self) {
+        self.containers.remove_all();
+    }
+}
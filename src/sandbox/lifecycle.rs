@@ -0,0 +1,351 @@
+use std::{
+    fs,
+    path::Path,
+    process::{self, Command, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+use log::{info, warn};
+
+use crate::{config::DriverConfig, metrics::Usage};
+
+use super::{
+    cgroup_parent_args, cgroup_parent_dir, cgroup_usage, client_usage, namespace,
+    remove_cgroup_parent, SandboxKind,
+};
+
+/// Label of every container the driver creates, so that stale ones can be told apart from
+/// containers of anything else sharing the engine
+pub const LABEL: &str = "codecharacter-driver";
+
+/// Label naming the driver instance that created a container, see `instance_id`
+pub const INSTANCE_LABEL: &str = "codecharacter-driver.instance";
+
+/// Prefix of the idle containers of the warm pool, see `pool::WarmPool`
+pub const POOL_PREFIX: &str = "pool_";
+
+/// `{hostname}/{pid}/{start time}` of this driver. The start time (in clock ticks since boot)
+/// tells a restarted driver apart from an earlier one that happened to get the same pid.
+pub fn instance_id() -> &'static str {
+    static ID: OnceLock<String> = OnceLock::new();
+    ID.get_or_init(|| {
+        let (pid, start) = local_instance();
+        format!("{}/{pid}/{start}", hostname())
+    })
+}
+
+/// The pid and start time of this driver, `instance_id` without the host for what only
+/// drivers on this host share
+pub fn local_instance() -> (u32, u64) {
+    static INSTANCE: OnceLock<(u32, u64)> = OnceLock::new();
+    *INSTANCE.get_or_init(|| {
+        let pid = process::id();
+        (pid, start_time(pid).unwrap_or_default())
+    })
+}
+
+fn hostname() -> String {
+    nix::sys::utsname::uname().nodename().to_owned()
+}
+
+/// Field 22 of `/proc/{pid}/stat`, after the command name which may contain anything
+fn start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Whether the driver instance `id` is gone. Only instances on this host can be checked, for
+/// any other (or a malformed id) this is `false`.
+pub fn is_dead_instance(id: &str) -> bool {
+    let mut parts = id.rsplitn(3, '/');
+    let (Some(start), Some(pid), Some(host)) = (parts.next(), parts.next(), parts.next()) else {
+        return false;
+    };
+    let (Ok(start), Ok(pid)) = (start.parse::<u64>(), pid.parse::<u32>()) else {
+        return false;
+    };
+    host == hostname() && is_dead_local_instance(pid, start)
+}
+
+/// Whether the driver instance on this host with `pid`, started at `start`, is gone
+pub fn is_dead_local_instance(pid: u32, start: u64) -> bool {
+    start_time(pid) != Some(start)
+}
+
+/// `{pid}.{start time}-{attempt}`, appended to the names of what an attempt at a game creates
+/// on the host, e.g. its directory. The same game may be run by another driver at the same
+/// time after the broker redelivered its request, every attempt gets names of its own.
+pub fn attempt_suffix() -> String {
+    static ATTEMPTS: AtomicU64 = AtomicU64::new(0);
+    let (pid, start) = local_instance();
+    let attempt = ATTEMPTS.fetch_add(1, Ordering::Relaxed);
+    format!("{pid}.{start}-{attempt}")
+}
+
+/// Splits a name ending in an `attempt_suffix` into what precedes it and whether the driver
+/// that created it is gone
+pub fn parse_attempt(name: &str) -> Option<(&str, bool)> {
+    let mut parts = name.rsplitn(3, '-');
+    let (Some(attempt), Some(instance), Some(prefix)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    attempt.parse::<u64>().ok()?;
+    let (pid, start) = instance.split_once('.')?;
+    let (pid, start) = (pid.parse().ok()?, start.parse().ok()?);
+    Some((prefix, is_dead_local_instance(pid, start)))
+}
+
+/// Every container started for one game through a docker compatible CLI.
+///
+/// Killing the CLI client does not stop its container, so containers are stopped and removed
+/// by name, on `remove_all` once a game failed and at the latest when this is dropped.
+pub struct Containers {
+    cli: &'static str,
+    /// `CONTAINER_CGROUP_PARENT`, see `super::cgroup_parent_args`
+    cgroup_parent: Option<String>,
+    /// Every container with the name it was created as, which also names its cgroup
+    names: Mutex<Vec<(String, String)>>,
+}
+
+impl Containers {
+    pub fn new(cli: &'static str, cgroup_parent: Option<String>) -> Self {
+        Containers {
+            cli,
+            cgroup_parent,
+            names: Mutex::new(vec![]),
+        }
+    }
+
+    /// Tracks `name` before its container gets created, so that one that is only half
+    /// started is removed too
+    pub fn track(&self, name: &str) {
+        self.names
+            .lock()
+            .unwrap()
+            .push((name.to_owned(), name.to_owned()));
+    }
+
+    /// `name` is a pooled container, created as `created_as` and renamed since
+    pub fn track_pooled(&self, name: &str, created_as: &str) {
+        for (tracked, created) in self.names.lock().unwrap().iter_mut() {
+            if tracked == name {
+                *created = created_as.to_owned();
+            }
+        }
+    }
+
+    /// Extra `run` / `create` arguments for the container created as `name`
+    pub fn cgroup_args(&self, name: &str) -> Vec<String> {
+        cgroup_parent_args(self.cgroup_parent.as_deref(), name)
+    }
+
+    /// What the exited container `name` used, read from its cgroup when it has one. Without
+    /// one, only the wall time of `usage` says something about it.
+    pub fn usage(&self, name: &str, usage: Usage) -> Usage {
+        let usage = client_usage(usage);
+        let Some(parent) = &self.cgroup_parent else {
+            return usage;
+        };
+        let names = self.names.lock().unwrap();
+        let created_as = names
+            .iter()
+            .find(|(tracked, _)| tracked == name)
+            .map_or(name, |(_, created)| created.as_str());
+        cgroup_usage(&cgroup_parent_dir(parent, created_as), usage)
+    }
+
+    /// Stops and removes every container tracked so far, together with its cgroup
+    pub fn remove_all(&self) {
+        let (names, created): (Vec<_>, Vec<_>) = std::mem::take(&mut *self.names.lock().unwrap())
+            .into_iter()
+            .unzip();
+        remove_containers(self.cli, &names);
+        for created_as in created {
+            remove_cgroup_parent(self.cgroup_parent.as_deref(), &created_as);
+        }
+    }
+}
+
+impl Drop for Containers {
+    fn drop(&mut self) {
+        self.remove_all();
+    }
+}
+
+/// Force removes the containers, stopping any that still run. Best effort, containers that
+/// never got created are fine.
+pub fn remove_containers(cli: &str, containers: &[String]) {
+    if containers.is_empty() {
+        return;
+    }
+    let _ = Command::new(cli)
+        .args(["rm", "-f"])
+        .args(containers)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// Whether `name` follows the naming scheme of the driver: `{game_id}[_{tag}]_{role}` for the
+/// phases of a game and `pool_{role}_{pid}_{n}` for idle pool containers
+pub fn is_driver_container(name: &str, roles: &[String]) -> bool {
+    roles.iter().any(|role| {
+        name.strip_suffix(role.as_str())
+            .is_some_and(|game| game.len() > 1 && game.ends_with('_'))
+            || name
+                .strip_prefix(POOL_PREFIX)
+                .and_then(|rest| rest.strip_prefix(role.as_str()))
+                .is_some_and(|rest| rest.starts_with('_'))
+    })
+}
+
+/// Removes the containers a previous driver left behind, e.g. after it crashed. Only labelled
+/// containers following the naming scheme of one of `roles` and created by a driver instance
+/// that is no longer alive are touched, live drivers sharing the engine keep theirs.
+/// Returns how many were removed.
+pub fn reap_stale(cli: &str, roles: &[String]) -> usize {
+    let out = match Command::new(cli)
+        .args(["ps", "-a", "--filter"])
+        .arg(format!("label={LABEL}"))
+        .arg("--format")
+        .arg(format!(
+            "{{{{.Names}}}}\t{{{{.Label \"{INSTANCE_LABEL}\"}}}}"
+        ))
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(out) if out.status.success() => out,
+        Ok(out) => {
+            warn!(
+                "Unable to list stale containers: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            return 0;
+        }
+        Err(e) => {
+            warn!("Unable to list stale containers: {e}");
+            return 0;
+        }
+    };
+    let stale = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter_map(|line| line.trim().split_once('\t'))
+        .filter(|(name, instance)| {
+            is_driver_container(name, roles) && is_dead_instance(instance.trim())
+        })
+        .map(|(name, _)| name.to_owned())
+        .collect::<Vec<_>>();
+    if !stale.is_empty() {
+        info!(
+            "Removing {} stale containers: {}",
+            stale.len(),
+            stale.join(" ")
+        );
+        remove_containers(cli, &stale);
+    }
+    stale.len()
+}
+
+/// Reaps stale containers of the configured backend (the cgroups of the namespace backend),
+/// unless `REAP_STALE_CONTAINERS` is off
+pub fn reap_from_config(config: &DriverConfig) -> usize {
+    if !config.reap_stale_containers {
+        return 0;
+    }
+    match config.sandbox.cli() {
+        Some((cli, _)) => reap_stale(cli, &config.roles()),
+        None if config.sandbox == SandboxKind::Namespace => {
+            namespace::reap_stale(Path::new(&config.namespace.cgroup_root))
+        }
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use super::{instance_id, is_dead_instance, is_driver_container, reap_stale};
+    use crate::utils::TestDir;
+
+    #[test]
+    fn naming_scheme() {
+        let roles = ["cpp_compiler", "cpp_runner", "simulator"].map(str::to_owned);
+
+        assert!(is_driver_container("42_cpp_runner", &roles));
+        assert!(is_driver_container("42_attacker_cpp_compiler", &roles));
+        assert!(is_driver_container("42_defender_simulator", &roles));
+        assert!(is_driver_container("pool_simulator_1234_0", &roles));
+        assert!(is_driver_container("pool_cpp_runner_1234_7", &roles));
+
+        assert!(!is_driver_container("simulator", &roles));
+        assert!(!is_driver_container("_simulator", &roles));
+        assert!(!is_driver_container("42_java_runner", &roles));
+        assert!(!is_driver_container("42_simulator_backup", &roles));
+        assert!(!is_driver_container("pool_java_runner_1234_0", &roles));
+        assert!(!is_driver_container("redis", &roles));
+    }
+
+    #[test]
+    fn instances() {
+        let own = instance_id();
+        assert!(!is_dead_instance(own));
+
+        let (host_and_pid, start) = own.rsplit_once('/').unwrap();
+        let restarted = format!("{host_and_pid}/{}", start.parse::<u64>().unwrap() + 1);
+        assert!(is_dead_instance(&restarted));
+        let (host, _) = host_and_pid.rsplit_once('/').unwrap();
+        assert!(is_dead_instance(&format!("{host}/{}/1", u32::MAX)));
+
+        // Other hosts cannot be checked
+        assert!(!is_dead_instance("elsewhere/1/1"));
+        assert!(!is_dead_instance(""));
+        assert!(!is_dead_instance("garbage"));
+    }
+
+    #[test]
+    fn reaps_containers_of_dead_instances() {
+        // Lists driver containers of a dead, a live and a foreign instance, one of an older
+        // driver without an instance and one that only carries the label, logs the rest
+        let own = instance_id();
+        let (host_and_pid, start) = own.rsplit_once('/').unwrap();
+        let dead = format!("{host_and_pid}/{}", start.parse::<u64>().unwrap() + 1);
+        let listing = [
+            format!("7_simulator\t{dead}"),
+            format!("backup\t{dead}"),
+            format!("7_cpp_runner\t{dead}"),
+            format!("8_simulator\t{own}"),
+            "9_simulator\telsewhere/1/1".to_owned(),
+            "10_simulator\t".to_owned(),
+        ]
+        .join("\\n");
+
+        let dir = TestDir::new("lifecycle_reaps_containers_of_dead_instances");
+        let (cli, calls) = (dir.join("cli"), dir.join("calls"));
+        fs::write(
+            &cli,
+            format!(
+                "#!/bin/sh\necho \"$@\" >> {}\n\
+                 [ \"$1\" = ps ] && printf '{listing}\\n'\nexit 0\n",
+                calls.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&cli, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let roles = ["cpp_runner", "simulator"].map(str::to_owned);
+        assert_eq!(reap_stale(cli.to_str().unwrap(), &roles), 2);
+        assert_eq!(
+            fs::read_to_string(&calls).unwrap(),
+            "ps -a --filter label=codecharacter-driver --format \
+             {{.Names}}\t{{.Label \"codecharacter-driver.instance\"}}\n\
+             rm -f 7_simulator 7_cpp_runner\n"
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use std::{
+    fmt::Display,
+    fs, io,
+    os::{linux::process::CommandExt, unix::process::ExitStatusExt},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::{getpgid, Pid},
+};
+
+use crate::{
+    config::{DriverConfig, ResourceProfile},
+    error::SimulatorError,
+    metrics::Usage,
+};
+
+pub mod cli;
+pub mod lifecycle;
+pub mod namespace;
+pub mod native;
+pub mod pool;
+
+/// Where the simulator of a player-vs-player match finds the participant FIFOs
+pub const FIFO_MOUNT: &str = "/fifos/";
+
+/// Where cgroup v2 is mounted, `CONTAINER_CGROUP_PARENT` is a path below it
+const CGROUP_MOUNT: &str = "/sys/fs/cgroup";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub source: String,
+    pub target: String,
+}
+
+impl Mount {
+    pub fn new(source: String, target: &str) -> Self {
+        Mount {
+            source,
+            target: target.to_owned(),
+        }
+    }
+}
+
+/// Everything a backend needs to know to start one compile or run phase.
+#[derive(Debug, Clone)]
+pub struct ContainerSpec {
+    pub name: String,
+    /// What the container does, e.g. `cpp_compiler`, `python_runner` or `simulator`
+    pub role: String,
+    pub image: String,
+    pub mounts: Vec<Mount>,
+    pub limits: ResourceProfile,
+    /// Passed after the image, empty keeps the default command of the image
+    pub command: Vec<String>,
+    /// Keep stdin attached, needed for anything talking over the FIFOs
+    pub interactive: bool,
+    pub current_dir: Option<String>,
+}
+
+/// Starts containers described by the runners.
+///
+/// The returned child always has its stderr piped and a pidfd attached, so that it
+/// can be registered with `poll::epoll_entry::Process`.
+pub trait SandboxBackend: Send + Sync {
+    fn spawn(
+        &self,
+        spec: &ContainerSpec,
+        stdin: Stdio,
+        stdout: Stdio,
+    ) -> Result<Child, SimulatorError>;
+
+    /// Identifies what `spawn` would execute for the spec (e.g. the image digest), so that
+    /// compiled artifacts are never reused across compiler versions
+    fn fingerprint(&self, spec: &ContainerSpec) -> Result<String, SimulatorError>;
+
+    /// Whether the process of `spec` was killed for going over its memory limit, asked once
+    /// it has been reaped with a failing status
+    fn out_of_memory(&self, _spec: &ContainerSpec) -> bool {
+        false
+    }
+
+    /// The signal that killed a process spawned by this backend, if any
+    fn exit_signal(&self, status: ExitStatus) -> Option<i32> {
+        status.signal()
+    }
+
+    /// What the process of `spec` used, given what `metrics::wait` measured of the spawned
+    /// child. By default the child is the process itself.
+    fn usage(&self, _spec: &ContainerSpec, usage: Usage) -> Usage {
+        usage
+    }
+
+    /// Stops and removes everything spawned so far, once the game failed. Killing the spawned
+    /// child is not enough where it is only a client, like the docker CLI.
+    fn cleanup(&self) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SandboxKind {
+    Docker,
+    Podman,
+    Native,
+    Namespace,
+}
+
+impl SandboxKind {
+    /// The docker compatible CLI of the backend with its extra `run` / `create` arguments,
+    /// `None` for the backends that do without a container engine
+    pub fn cli(&self) -> Option<(&'static str, Vec<String>)> {
+        match self {
+            SandboxKind::Docker => Some(("docker", vec![])),
+            SandboxKind::Podman => Some(("podman", vec!["--userns=keep-id".to_owned()])),
+            SandboxKind::Native | SandboxKind::Namespace => None,
+        }
+    }
+}
+
+impl FromStr for SandboxKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "docker" => Ok(SandboxKind::Docker),
+            "podman" => Ok(SandboxKind::Podman),
+            "native" => Ok(SandboxKind::Native),
+            "namespace" => Ok(SandboxKind::Namespace),
+            other => Err(format!(
+                "unknown sandbox backend {other}, expected docker, podman, native or namespace"
+            )),
+        }
+    }
+}
+
+impl Display for SandboxKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SandboxKind::Docker => write!(f, "docker"),
+            SandboxKind::Podman => write!(f, "podman"),
+            SandboxKind::Native => write!(f, "native"),
+            SandboxKind::Namespace => write!(f, "namespace"),
+        }
+    }
+}
+
+/// `pool` is shared by every game, it only applies to the docker and podman backends
+pub fn from_config(
+    config: &DriverConfig,
+    pool: Option<Arc<pool::WarmPool>>,
+) -> Arc<dyn SandboxBackend> {
+    match config.sandbox {
+        SandboxKind::Docker | SandboxKind::Podman => {
+            let (cli, extra_args) = config.sandbox.cli().unwrap();
+            Arc::new(cli::Cli::new(
+                cli,
+                extra_args,
+                pool,
+                config.container_cgroup_parent.clone(),
+            ))
+        }
+        SandboxKind::Native => Arc::new(native::Native::new(config.native_commands.clone())),
+        SandboxKind::Namespace => Arc::new(namespace::Namespace::new(config.namespace.clone())),
+    }
+}
+
+/// Soft and hard `RLIMIT_CPU` for a CPU time limit in seconds. The process gets `SIGXCPU` once
+/// it used up its time, which tells a CPU time kill apart from any other `SIGKILL`. The hard
+/// limit a second later still kills processes that ignore it.
+pub fn cpu_rlimit(cpu_time_limit: u64) -> (u64, u64) {
+    (cpu_time_limit, cpu_time_limit + 1)
+}
+
+/// Arguments shared by the docker compatible CLIs, from `run` up to (not including) the image
+pub fn run_args(spec: &ContainerSpec) -> Vec<String> {
+    let limits = &spec.limits;
+    let (cpu_soft, cpu_hard) = cpu_rlimit(limits.cpu_time_limit);
+    let mut args = vec![
+        "run".to_owned(),
+        format!("--memory={}", limits.memory),
+        format!("--memory-swap={}", limits.memory_swap),
+        format!("--cpus={}", limits.cpus),
+        "--ulimit".to_owned(),
+        format!("cpu={cpu_soft}:{cpu_hard}"),
+    ];
+    if !limits.jvm_flags.is_empty() {
+        args.push("-e".to_owned());
+        args.push(format!("JAVA_TOOL_OPTIONS={}", limits.jvm_flags.join(" ")));
+    }
+    // Not `--rm`, the container is inspected after it exited and removed with the backend
+    args.extend([
+        "--label".to_owned(),
+        lifecycle::LABEL.to_owned(),
+        "--label".to_owned(),
+        format!("{}={}", lifecycle::INSTANCE_LABEL, lifecycle::instance_id()),
+        "--name".to_owned(),
+        spec.name.clone(),
+    ]);
+    if spec.interactive {
+        args.push("-i".to_owned());
+    }
+    for mount in &spec.mounts {
+        args.push("-v".to_owned());
+        args.push(format!("{}:{}", mount.source, mount.target));
+    }
+    args
+}
+
+/// Spawns a fully built CLI command with the stdio layout every backend promises
+pub fn spawn_command(
+    mut command: Command,
+    spec: &ContainerSpec,
+    stdin: Stdio,
+    stdout: Stdio,
+) -> Result<Child, SimulatorError> {
+    if let Some(dir) = &spec.current_dir {
+        command.current_dir(dir);
+    }
+    command
+        .create_pidfd(true)
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            SimulatorError::UnidentifiedError(format!("Couldnt spawn {}: {err}", spec.name))
+        })
+}
+
+/// Kills `child` together with its process group when it leads one, like the shell of the
+/// native backend with everything it started. Has to happen before the child is reaped, until
+/// then no other process can take over its group.
+pub fn kill(child: &mut Child) -> io::Result<()> {
+    let pid = Pid::from_raw(child.id() as i32);
+    if getpgid(Some(pid)) == Ok(pid) {
+        let _ = killpg(pid, Signal::SIGKILL);
+    }
+    child.kill()
+}
+
+/// The spawned child of the docker compatible CLIs is only the client, so of its usage
+/// only the wall time says something about the container
+pub fn client_usage(usage: Usage) -> Usage {
+    Usage {
+        cpu_time: None,
+        peak_memory: None,
+        ..usage
+    }
+}
+
+/// `--cgroup-parent` arguments giving the container created as `container` a cgroup of its
+/// own below `parent`. The engine removes the container's cgroup once it exited, this one
+/// stays until `remove_cgroup_parent`.
+pub fn cgroup_parent_args(parent: Option<&str>, container: &str) -> Vec<String> {
+    match parent {
+        Some(parent) => vec![format!(
+            "--cgroup-parent={}/{container}",
+            parent.trim_end_matches('/')
+        )],
+        None => vec![],
+    }
+}
+
+/// Directory of the cgroup of `cgroup_parent_args`
+pub fn cgroup_parent_dir(parent: &str, container: &str) -> PathBuf {
+    Path::new(CGROUP_MOUNT)
+        .join(parent.trim_matches('/'))
+        .join(container)
+}
+
+/// Removes the then empty cgroup of `cgroup_parent_args`, best effort
+pub fn remove_cgroup_parent(parent: Option<&str>, container: &str) {
+    if let Some(parent) = parent {
+        let _ = fs::remove_dir(cgroup_parent_dir(parent, container));
+    }
+}
+
+/// CPU time and peak memory of everything that ran in the cgroup `dir`, including processes
+/// that are gone, or those of `usage` where the cgroup has none. `memory.peak` needs Linux
+/// 5.19.
+pub fn cgroup_usage(dir: &Path, usage: Usage) -> Usage {
+    let read = |file: &str| fs::read_to_string(dir.join(file)).ok();
+    Usage {
+        cpu_time: read("cpu.stat")
+            .and_then(|stat| cpu_usage(&stat))
+            .or(usage.cpu_time),
+        peak_memory: read("memory.peak")
+            .and_then(|peak| peak.trim().parse().ok())
+            .or(usage.peak_memory),
+        ..usage
+    }
+}
+
+/// `usage_usec` of a `cpu.stat` file
+pub fn cpu_usage(stat: &str) -> Option<Duration> {
+    stat.lines()
+        .find_map(|line| line.strip_prefix("usage_usec "))
+        .and_then(|usec| usec.trim().parse().ok())
+        .map(Duration::from_micros)
+}
+
+/// `.State.OOMKilled` of an exited container
+pub fn oom_killed(cli: &str, container: &str) -> bool {
+    Command::new(cli)
+        .args(["inspect", "--format", "{{.State.OOMKilled}}", container])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|out| {
+            out.status.success() && String::from_utf8_lossy(&out.stdout).trim() == "true"
+        })
+}
+
+/// Image id as reported by `{cli} image inspect`
+pub fn image_digest(cli: &str, image: &str) -> Result<String, SimulatorError> {
+    let out = Command::new(cli)
+        .args(["image", "inspect", "--format", "{{.Id}}", image])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| {
+            SimulatorError::UnidentifiedError(format!("Couldnt inspect image {image}: {err}"))
+        })?;
+    if !out.status.success() {
+        return Err(SimulatorError::UnidentifiedError(format!(
+            "Couldnt inspect image {image}: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, time::Duration};
+
+    use super::{
+        cgroup_parent_args, cgroup_parent_dir, cgroup_usage, cpu_usage, lifecycle::instance_id,
+        run_args, ContainerSpec, Mount,
+    };
+    use crate::{
+        config::ResourceProfile,
+        metrics::Usage,
+        utils::{example_spec, TestDir},
+    };
+
+    #[test]
+    fn docker_run_args() {
+        let mut spec = ContainerSpec {
+            mounts: vec![Mount::new("/tmp/1/run.jar".to_owned(), "/run.jar")],
+            ..example_spec("java_runner")
+        };
+        spec.limits = ResourceProfile {
+            cpus: 1.5,
+            memory: "256m".to_owned(),
+            memory_swap: "256m".to_owned(),
+            jvm_flags: vec!["-Xmx200m".to_owned(), "-Xss8m".to_owned()],
+            ..spec.limits
+        };
+
+        assert_eq!(
+            run_args(&spec),
+            vec![
+                "run",
+                "--memory=256m",
+                "--memory-swap=256m",
+                "--cpus=1.5",
+                "--ulimit",
+                "cpu=10:11",
+                "-e",
+                "JAVA_TOOL_OPTIONS=-Xmx200m -Xss8m",
+                "--label",
+                "codecharacter-driver",
+                "--label",
+                &format!("codecharacter-driver.instance={}", instance_id()),
+                "--name",
+                "1_java_runner",
+                "-i",
+                "-v",
+                "/tmp/1/run.jar:/run.jar",
+            ]
+        );
+    }
+
+    #[test]
+    fn cpu_stat_usage() {
+        let stat = "usage_usec 1534021\nuser_usec 1200000\nsystem_usec 334021\n";
+        assert_eq!(cpu_usage(stat), Some(Duration::from_micros(1534021)));
+        assert_eq!(cpu_usage("nr_periods 0\n"), None);
+    }
+
+    #[test]
+    fn usage_of_a_cgroup() {
+        let dir = TestDir::new("sandbox_usage_of_a_cgroup");
+        let client = Usage {
+            wall_time: Duration::from_secs(3),
+            cpu_time: None,
+            peak_memory: None,
+        };
+
+        // No cgroup files, e.g. a kernel without memory.peak
+        assert_eq!(cgroup_usage(&dir, client), client);
+
+        fs::write(
+            dir.join("cpu.stat"),
+            "usage_usec 2500000\nuser_usec 2500000\n",
+        )
+        .unwrap();
+        fs::write(dir.join("memory.peak"), "52428800\n").unwrap();
+        assert_eq!(
+            cgroup_usage(&dir, client),
+            Usage {
+                wall_time: Duration::from_secs(3),
+                cpu_time: Some(Duration::from_millis(2500)),
+                peak_memory: Some(50 << 20),
+            }
+        );
+    }
+
+    #[test]
+    fn cgroup_parent_of_a_container() {
+        assert!(cgroup_parent_args(None, "1_simulator").is_empty());
+        assert_eq!(
+            cgroup_parent_args(Some("/codecharacter/"), "1_simulator"),
+            vec!["--cgroup-parent=/codecharacter/1_simulator"]
+        );
+        assert_eq!(
+            cgroup_parent_dir("/codecharacter/", "1_simulator"),
+            Path::new("/sys/fs/cgroup/codecharacter/1_simulator")
+        );
+    }
+}
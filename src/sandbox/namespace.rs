@@ -0,0 +1,714 @@
+use std::{
+    ffi::{CStr, CString},
+    fs, io,
+    os::linux::process::CommandExt as LinuxCommandExt,
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{Mutex, Once},
+    thread,
+    time::Duration,
+};
+
+use log::{info, warn};
+use nix::{
+    libc,
+    mount::{mount, umount2, MntFlags, MsFlags},
+    sched::{unshare, CloneFlags},
+    sys::{
+        resource::{setrlimit, Resource},
+        signal::{kill, signal, SigHandler, Signal},
+        statvfs::{statvfs, FsFlags},
+        wait::{waitpid, WaitStatus},
+    },
+    unistd::{chdir, fork, getgid, getpid, getuid, pivot_root, ForkResult, Pid},
+};
+
+use crate::{
+    config::{memory_in_bytes, NamespaceConfig, ResourceProfile},
+    error::SimulatorError,
+    metrics::Usage,
+};
+
+use super::{
+    cgroup_usage, cpu_rlimit,
+    lifecycle::{attempt_suffix, parse_attempt},
+    ContainerSpec, SandboxBackend,
+};
+
+const CPU_PERIOD: u64 = 100_000;
+const PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+const DEVICES: [&str; 3] = ["null", "zero", "urandom"];
+
+/// Sandbox built directly on Linux primitives instead of a container engine.
+///
+/// Every phase gets fresh user, mount, pid, network, ipc and uts namespaces, a cgroup
+/// under `cgroup_root` enforcing the memory, swap, CPU and pids limits, `RLIMIT_CPU`, and
+/// the role's rootfs mounted read-only as `/` with the spec's mounts bound on top. The root is
+/// switched with `pivot_root` and the process execs without any capability.
+///
+/// The spawned child stays outside the new pid namespace and only relays the exit
+/// status of the sandboxed process (pid 1 inside), so the driver still gets a pidfd whose
+/// exit mirrors the player's. Commands must use absolute paths, the working directory
+/// inside the sandbox is `/`.
+pub struct Namespace {
+    config: NamespaceConfig,
+    /// Cgroup of every spawned phase by the name of its spec
+    cgroups: Mutex<Vec<(String, PathBuf)>>,
+}
+
+/// Everything the forked child needs, prepared up front since it must not allocate
+struct Setup {
+    cgroup_procs: CString,
+    /// Soft and hard `RLIMIT_CPU`
+    cpu_rlimit: (u64, u64),
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+    rootfs: CString,
+    /// (host source, target below rootfs)
+    binds: Vec<(CString, CString)>,
+    devices: Vec<(CString, CString)>,
+    proc_dir: CString,
+}
+
+impl Namespace {
+    pub fn new(config: NamespaceConfig) -> Self {
+        Namespace {
+            config,
+            cgroups: Mutex::new(vec![]),
+        }
+    }
+
+    fn create_cgroup(&self, name: &str, limits: &ResourceProfile) -> io::Result<PathBuf> {
+        let root = Path::new(&self.config.cgroup_root);
+        // Controllers have to be enabled for children, usually already done when delegating
+        let _ = fs::write(root.join("cgroup.subtree_control"), "+cpu +memory +pids");
+
+        // The same game may run in another driver at the same time
+        let dir = root.join(format!("{name}-{}", attempt_suffix()));
+        fs::create_dir(&dir)?;
+        self.cgroups
+            .lock()
+            .unwrap()
+            .push((name.to_owned(), dir.clone()));
+
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidInput, what.to_owned());
+        let memory = memory_in_bytes(&limits.memory).ok_or_else(|| invalid("memory limit"))?;
+        let memory_swap =
+            memory_in_bytes(&limits.memory_swap).ok_or_else(|| invalid("memory swap limit"))?;
+
+        fs::write(dir.join("memory.max"), memory.to_string())?;
+        // Like docker, the swap limit includes the memory limit. Without swap accounting
+        // there is nothing to limit.
+        if dir.join("memory.swap.max").exists() {
+            fs::write(
+                dir.join("memory.swap.max"),
+                memory_swap.saturating_sub(memory).to_string(),
+            )?;
+        } else {
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| {
+                warn!(
+                    "{} has no swap accounting, swap is not limited",
+                    root.display()
+                )
+            });
+        }
+        fs::write(dir.join("cpu.max"), cpu_max(limits.cpus))?;
+        fs::write(dir.join("pids.max"), self.config.pids_limit.to_string())?;
+        Ok(dir)
+    }
+
+    /// The cgroup the latest phase spawned as `name` ran in
+    fn cgroup(&self, name: &str) -> Option<PathBuf> {
+        self.cgroups
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|(spawned, _)| spawned == name)
+            .map(|(_, dir)| dir.clone())
+    }
+}
+
+/// Removes the cgroups below `root` left behind by drivers that are no longer alive, along
+/// with whatever still runs in them. Returns how many were removed.
+pub fn reap_stale(root: &Path) -> usize {
+    let Ok(entries) = fs::read_dir(root) else {
+        return 0;
+    };
+    let stale = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .filter(|entry| {
+            let name = entry.file_name();
+            name.to_str()
+                .and_then(parse_attempt)
+                .is_some_and(|(_, dead)| dead)
+        })
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    if !stale.is_empty() {
+        info!(
+            "Removing {} stale cgroups below {}",
+            stale.len(),
+            root.display()
+        );
+    }
+    for dir in &stale {
+        remove_cgroup(dir);
+    }
+    stale.len()
+}
+
+impl SandboxBackend for Namespace {
+    fn spawn(
+        &self,
+        spec: &ContainerSpec,
+        stdin: Stdio,
+        stdout: Stdio,
+    ) -> Result<Child, SimulatorError> {
+        let role = self.config.roles.get(&spec.role).ok_or_else(|| {
+            SimulatorError::UnidentifiedError(format!(
+                "No namespace sandbox configured for {}",
+                spec.role
+            ))
+        })?;
+        let map_err = |err: io::Error| {
+            SimulatorError::UnidentifiedError(format!(
+                "Couldnt prepare the sandbox for {}: {err}",
+                spec.name
+            ))
+        };
+
+        let cgroup = self
+            .create_cgroup(&spec.name, &spec.limits)
+            .map_err(map_err)?;
+        let rootfs = PathBuf::from(&role.rootfs);
+        let below_rootfs = |target: &str| cstring(&rootfs.join(target.trim_start_matches('/')));
+
+        let setup = Setup {
+            cgroup_procs: cstring(&cgroup.join("cgroup.procs")).map_err(map_err)?,
+            cpu_rlimit: cpu_rlimit(spec.limits.cpu_time_limit),
+            uid_map: format!("0 {} 1", getuid()).into_bytes(),
+            gid_map: format!("0 {} 1", getgid()).into_bytes(),
+            rootfs: cstring(&rootfs).map_err(map_err)?,
+            binds: spec
+                .mounts
+                .iter()
+                .map(|m| Ok((cstring(Path::new(&m.source))?, below_rootfs(&m.target)?)))
+                .collect::<io::Result<_>>()
+                .map_err(map_err)?,
+            devices: DEVICES
+                .iter()
+                .filter(|dev| rootfs.join("dev").join(dev).exists())
+                .map(|dev| {
+                    let host = format!("/dev/{dev}");
+                    Ok((cstring(Path::new(&host))?, below_rootfs(&host)?))
+                })
+                .collect::<io::Result<_>>()
+                .map_err(map_err)?,
+            proc_dir: below_rootfs("/proc").map_err(map_err)?,
+        };
+
+        let (program, args) = role.command.split_first().ok_or_else(|| {
+            SimulatorError::UnidentifiedError(format!("Empty sandbox command for {}", spec.role))
+        })?;
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .env_clear()
+            .env("PATH", PATH)
+            .env("HOME", "/tmp");
+        if !spec.limits.jvm_flags.is_empty() {
+            command.env("JAVA_TOOL_OPTIONS", spec.limits.jvm_flags.join(" "));
+        }
+
+        spawn_sandboxed(command, setup, stdin, stdout).map_err(|err| {
+            SimulatorError::UnidentifiedError(format!("Couldnt spawn {}: {err}", spec.name))
+        })
+    }
+
+    fn fingerprint(&self, spec: &ContainerSpec) -> Result<String, SimulatorError> {
+        Ok(self
+            .config
+            .roles
+            .get(&spec.role)
+            .map(|role| format!("{} {}", role.rootfs, role.command.join(" ")))
+            .unwrap_or_default())
+    }
+
+    /// The cgroup counts every kill of its OOM killer in `memory.events`
+    fn out_of_memory(&self, spec: &ContainerSpec) -> bool {
+        self.cgroup(&spec.name).is_some_and(|dir| {
+            fs::read_to_string(dir.join("memory.events")).is_ok_and(|events| oom_kills(&events) > 0)
+        })
+    }
+
+    /// Read from the cgroup of the phase, which also counts processes the sandboxed one
+    /// never waited for. Without `memory.peak` `wait4` is all there is.
+    fn usage(&self, spec: &ContainerSpec, usage: Usage) -> Usage {
+        match self.cgroup(&spec.name) {
+            Some(dir) => cgroup_usage(&dir, usage),
+            None => usage,
+        }
+    }
+
+    /// Kills whatever still runs in the cgroups of the game and removes them
+    fn cleanup(&self) {
+        for (_, dir) in self.cgroups.lock().unwrap().drain(..) {
+            remove_cgroup(&dir);
+        }
+    }
+}
+
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        self.cleanup();
+    }
+}
+
+/// `cpu.max` contents for a docker style `--cpus` value
+pub fn cpu_max(cpus: f64) -> String {
+    let quota = (cpus * CPU_PERIOD as f64).round().max(1000.0) as u64;
+    format!("{quota} {CPU_PERIOD}")
+}
+
+/// `oom_kill` of a `memory.events` file
+pub fn oom_kills(events: &str) -> u64 {
+    events
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .and_then(|kills| kills.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Kills whatever is still inside and removes the cgroup, best effort
+fn remove_cgroup(dir: &Path) {
+    // `cgroup.kill` needs Linux 5.14, before that every process is killed on its own
+    let killed =
+        dir.join("cgroup.kill").exists() && fs::write(dir.join("cgroup.kill"), "1").is_ok();
+    for _ in 0..10 {
+        if !killed {
+            kill_procs(dir);
+        }
+        if fs::remove_dir(dir).is_ok() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    warn!("Unable to remove the cgroup {}", dir.display());
+}
+
+/// Sends `SIGKILL` to every process listed in the cgroup's `cgroup.procs`
+fn kill_procs(dir: &Path) {
+    let Ok(procs) = fs::read_to_string(dir.join("cgroup.procs")) else {
+        return;
+    };
+    for pid in procs.lines().filter_map(|pid| pid.trim().parse().ok()) {
+        let _ = kill(Pid::from_raw(pid), Signal::SIGKILL);
+    }
+}
+
+/// Spawns `command` inside the namespaces and cgroup described by `setup`, returns as soon
+/// as the sandboxed process was executed
+fn spawn_sandboxed(
+    mut command: Command,
+    setup: Setup,
+    stdin: Stdio,
+    stdout: Stdio,
+) -> io::Result<Child> {
+    // SAFETY: the closure only issues syscalls on data prepared up front, apart from the
+    // relaying parent which never returns and leaves through _exit
+    unsafe {
+        command.pre_exec(move || enter_sandbox(&setup));
+    }
+
+    command
+        .create_pidfd(true)
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(Stdio::piped())
+        .spawn()
+}
+
+fn cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn os_err(err: nix::errno::Errno) -> io::Error {
+    io::Error::from_raw_os_error(err as i32)
+}
+
+fn write_file(path: &CStr, data: &[u8]) -> io::Result<()> {
+    // SAFETY: plain syscalls on a valid, NUL terminated path and an owned buffer
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let written = libc::write(fd, data.as_ptr() as *const libc::c_void, data.len());
+        libc::close(fd);
+        if written < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Runs in the forked child right before exec
+fn enter_sandbox(setup: &Setup) -> io::Result<()> {
+    write_file(&setup.cgroup_procs, b"0")?;
+    let (cpu_soft, cpu_hard) = setup.cpu_rlimit;
+    setrlimit(Resource::RLIMIT_CPU, Some(cpu_soft), Some(cpu_hard)).map_err(os_err)?;
+
+    unshare(
+        CloneFlags::CLONE_NEWUSER
+            | CloneFlags::CLONE_NEWNS
+            | CloneFlags::CLONE_NEWPID
+            | CloneFlags::CLONE_NEWNET
+            | CloneFlags::CLONE_NEWIPC
+            | CloneFlags::CLONE_NEWUTS,
+    )
+    .map_err(os_err)?;
+    write_file(
+        CStr::from_bytes_with_nul(b"/proc/self/setgroups\0").unwrap(),
+        b"deny",
+    )?;
+    write_file(
+        CStr::from_bytes_with_nul(b"/proc/self/uid_map\0").unwrap(),
+        &setup.uid_map,
+    )?;
+    write_file(
+        CStr::from_bytes_with_nul(b"/proc/self/gid_map\0").unwrap(),
+        &setup.gid_map,
+    )?;
+
+    // Only children of this process enter the new pid namespace
+    // SAFETY: the child continues with async-signal-safe calls only until exec
+    match unsafe { fork() }.map_err(os_err)? {
+        ForkResult::Child => {
+            // SAFETY: prctl with integer arguments
+            unsafe {
+                libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0);
+                libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0);
+            }
+            setup_mounts(setup)?;
+            drop_capabilities()
+        }
+        ForkResult::Parent { child } => {
+            // The relay never execs, so it would keep std's close-on-exec status pipe open
+            // and spawn would only return once the sandboxed process exited
+            close_all_fds();
+            loop {
+                match waitpid(child, None) {
+                    Ok(WaitStatus::Exited(_, code)) => unsafe { libc::_exit(code) },
+                    Ok(WaitStatus::Signaled(_, sig, _)) => {
+                        relay_signal(sig);
+                        unsafe { libc::_exit(128 + sig as i32) }
+                    }
+                    Err(nix::errno::Errno::EINTR) | Ok(_) => continue,
+                    Err(_) => unsafe { libc::_exit(1) },
+                }
+            }
+        }
+    }
+}
+
+/// Closes every file descriptor of the process, async-signal-safe
+fn close_all_fds() {
+    // SAFETY: plain syscalls with integer arguments
+    unsafe {
+        // close_range needs Linux 5.9, older kernels get the slow way
+        if libc::syscall(libc::SYS_close_range, 0, libc::c_uint::MAX, 0) != 0 {
+            for fd in 0..libc::sysconf(libc::_SC_OPEN_MAX).clamp(1024, 1 << 20) as libc::c_int {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+/// Dies from the same signal so that the driver sees the real cause
+fn relay_signal(sig: Signal) {
+    // SAFETY: resetting to the default disposition before raising the signal
+    unsafe {
+        let _ = signal(sig, SigHandler::SigDfl);
+    }
+    let _ = kill(getpid(), sig);
+}
+
+fn setup_mounts(setup: &Setup) -> io::Result<()> {
+    let none: Option<&CStr> = None;
+    let root = CStr::from_bytes_with_nul(b"/\0").unwrap();
+
+    mount(
+        none,
+        root,
+        none,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        none,
+    )
+    .map_err(os_err)?;
+    mount(
+        Some(setup.rootfs.as_c_str()),
+        setup.rootfs.as_c_str(),
+        none,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        none,
+    )
+    .map_err(os_err)?;
+
+    for (source, target) in setup.binds.iter().chain(setup.devices.iter()) {
+        mount(
+            Some(source.as_c_str()),
+            target.as_c_str(),
+            none,
+            MsFlags::MS_BIND,
+            none,
+        )
+        .map_err(os_err)?;
+    }
+
+    let proc = CStr::from_bytes_with_nul(b"proc\0").unwrap();
+    mount(
+        Some(proc),
+        setup.proc_dir.as_c_str(),
+        Some(proc),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        none,
+    )
+    .map_err(os_err)?;
+
+    // Flags locked by the outer mount have to be kept or the remount is refused
+    let mut flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+    let locked = statvfs(setup.rootfs.as_c_str()).map_err(os_err)?.flags();
+    for (fs_flag, ms_flag) in [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        if locked.contains(fs_flag) {
+            flags |= ms_flag;
+        }
+    }
+    mount(none, setup.rootfs.as_c_str(), none, flags, none).map_err(os_err)?;
+
+    // Unlike chroot, the old root is gone afterwards instead of only being out of sight
+    let dot = CStr::from_bytes_with_nul(b".\0").unwrap();
+    chdir(setup.rootfs.as_c_str()).map_err(os_err)?;
+    pivot_root(dot, dot).map_err(os_err)?;
+    umount2(dot, MntFlags::MNT_DETACH).map_err(os_err)?;
+    chdir(root).map_err(os_err)?;
+    Ok(())
+}
+
+/// `struct __user_cap_header_struct`
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+/// `struct __user_cap_data_struct`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+const CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// Leaves the process without any capability in its user namespace, so that it can neither
+/// undo the read-only mounts nor chroot its way out. With an empty bounding set exec does
+/// not hand them back to uid 0 either.
+fn drop_capabilities() -> io::Result<()> {
+    // SAFETY: prctl with integer arguments and capset on buffers that outlive the call
+    unsafe {
+        // Fails with EINVAL past the last capability of the kernel
+        for cap in 0..64 {
+            if libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) != 0
+                && io::Error::last_os_error().raw_os_error() != Some(libc::EINVAL)
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        libc::prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_CLEAR_ALL,
+            0,
+            0,
+            0,
+        );
+        let header = CapHeader {
+            version: CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let data = [CapData::default(); 2];
+        if libc::syscall(libc::SYS_capset, &header, data.as_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::CString,
+        fs,
+        os::unix::process::ExitStatusExt,
+        path::Path,
+        process::{Command, Stdio},
+        time::{Duration, Instant},
+    };
+
+    use nix::libc;
+
+    use super::{
+        cpu_max, cstring, oom_kills, reap_stale, remove_cgroup, spawn_sandboxed, Namespace, Setup,
+    };
+    use crate::{
+        config::{NamespaceConfig, ResourceProfile},
+        sandbox::lifecycle::local_instance,
+        utils::{example_spec, TestDir},
+    };
+
+    /// A rootfs in `dir` with the host's programs bound into it, joining no cgroup. The
+    /// host's `/` itself cannot be pivoted into.
+    fn host_setup(dir: &Path) -> Setup {
+        let mut binds = vec![];
+        for top in ["usr", "bin", "sbin", "lib", "lib64"] {
+            let host = Path::new("/").join(top);
+            if let Ok(link) = fs::read_link(&host) {
+                std::os::unix::fs::symlink(link, dir.join(top)).unwrap();
+            } else if host.is_dir() {
+                fs::create_dir(dir.join(top)).unwrap();
+                binds.push((cstring(&host).unwrap(), cstring(&dir.join(top)).unwrap()));
+            }
+        }
+        fs::create_dir(dir.join("proc")).unwrap();
+        Setup {
+            cgroup_procs: CString::new("/dev/null").unwrap(),
+            cpu_rlimit: (libc::RLIM_INFINITY, libc::RLIM_INFINITY),
+            uid_map: format!("0 {} 1", nix::unistd::getuid()).into_bytes(),
+            gid_map: format!("0 {} 1", nix::unistd::getgid()).into_bytes(),
+            rootfs: cstring(dir).unwrap(),
+            binds,
+            devices: vec![],
+            proc_dir: cstring(&dir.join("proc")).unwrap(),
+        }
+    }
+
+    #[test]
+    fn cpu_quota() {
+        assert_eq!(cpu_max(1.0), "100000 100000");
+        assert_eq!(cpu_max(1.5), "150000 100000");
+        assert_eq!(cpu_max(0.001), "1000 100000");
+    }
+
+    #[test]
+    fn memory_events_oom_kills() {
+        let events = "low 0\nhigh 0\nmax 12\noom 1\noom_kill 1\noom_group_kill 0\n";
+        assert_eq!(oom_kills(events), 1);
+        assert_eq!(oom_kills("low 0\n"), 0);
+    }
+
+    #[test]
+    fn cgroup_without_swap_accounting() {
+        // A plain directory has none of the interface files, like a host without swap accounting
+        let root = TestDir::new("namespace_cgroup_without_swap_accounting");
+        let namespace = Namespace::new(NamespaceConfig {
+            cgroup_root: root.to_str().unwrap().to_owned(),
+            pids_limit: 64,
+            ..Default::default()
+        });
+        let limits = ResourceProfile {
+            memory_swap: "200m".to_owned(),
+            ..example_spec("cpp_runner").limits
+        };
+
+        let dir = namespace.create_cgroup("1_cpp_runner", &limits).unwrap();
+        // Every attempt gets a cgroup of its own
+        assert_ne!(
+            namespace.create_cgroup("1_cpp_runner", &limits).unwrap(),
+            dir
+        );
+        assert_eq!(
+            namespace.cgroup("1_cpp_runner").unwrap().parent(),
+            Some(&*root)
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("memory.max")).unwrap(),
+            (100 << 20).to_string()
+        );
+        assert!(!dir.join("memory.swap.max").exists());
+    }
+
+    #[test]
+    fn only_cgroups_of_dead_instances_are_stale() {
+        let root = TestDir::new("namespace_only_cgroups_of_dead_instances_are_stale");
+        let (pid, start) = local_instance();
+        let live = format!("1_cpp_runner-{pid}.{start}-0");
+        let dead = format!("1_simulator-{pid}.{}-3", start + 1);
+        for name in [&live, &dead, &"codecharacter".to_owned()] {
+            fs::create_dir(root.join(name)).unwrap();
+        }
+
+        assert_eq!(reap_stale(&root), 1);
+        assert!(root.join(&live).exists());
+        assert!(!root.join(&dead).exists());
+        assert!(root.join("codecharacter").exists());
+    }
+
+    #[test]
+    fn cgroup_without_kill_file() {
+        // Linux before 5.14 has no cgroup.kill, the processes are killed one by one
+        let dir = TestDir::new("namespace_cgroup_without_kill_file");
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        fs::write(dir.join("cgroup.procs"), format!("{}\n", child.id())).unwrap();
+
+        remove_cgroup(&dir);
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGKILL));
+    }
+
+    #[test]
+    fn sandboxed_process_has_no_capabilities() {
+        let rootfs = TestDir::new("namespace_sandboxed_process_has_no_capabilities");
+        let setup = host_setup(&rootfs);
+        let mut command = Command::new("/bin/sh");
+        command.args(["-c", "grep -E '^Cap(Eff|Prm|Bnd)' /proc/self/status"]);
+
+        let child = spawn_sandboxed(command, setup, Stdio::null(), Stdio::piped()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "CapPrm:\t0000000000000000\n\
+             CapEff:\t0000000000000000\n\
+             CapBnd:\t0000000000000000\n"
+        );
+    }
+
+    #[test]
+    fn spawn_returns_while_the_sandboxed_process_runs() {
+        let rootfs = TestDir::new("namespace_spawn_returns_while_the_sandboxed_process_runs");
+        let setup = host_setup(&rootfs);
+        let mut command = Command::new("/bin/sh");
+        command.args(["-c", "sleep 2; exit 3"]);
+
+        let started = Instant::now();
+        let mut child = spawn_sandboxed(command, setup, Stdio::null(), Stdio::null()).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(child.try_wait().unwrap().is_none());
+
+        let status = child.wait().unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert!(status.signal().is_none());
+        assert!(started.elapsed() >= Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    os::unix::process::CommandExt,
+    process::{Child, Command, Stdio},
+};
+
+use nix::sys::resource::{setrlimit, Resource};
+
+use crate::error::SimulatorError;
+
+use super::{cpu_rlimit, spawn_command, ContainerSpec, SandboxBackend};
+
+/// Development backend running every phase as a plain host process.
+///
+/// Commands are executed with `sh -c` in a process group of their own inside the game
+/// directory, so they see the same files the containers would have mounted (`run.cpp`, `run`,
+/// `run.jar`, ...). Only the CPU time limit is enforced (through `RLIMIT_CPU`); memory and CPU
+/// share limits need one of the container backends.
+pub struct Native {
+    commands: HashMap<String, String>,
+}
+
+impl Native {
+    pub fn new(commands: HashMap<String, String>) -> Self {
+        Native { commands }
+    }
+}
+
+impl SandboxBackend for Native {
+    fn spawn(
+        &self,
+        spec: &ContainerSpec,
+        stdin: Stdio,
+        stdout: Stdio,
+    ) -> Result<Child, SimulatorError> {
+        let script = self.commands.get(&spec.role).ok_or_else(|| {
+            SimulatorError::UnidentifiedError(format!(
+                "No native command configured for {}",
+                spec.role
+            ))
+        })?;
+
+        let mut command = Command::new("sh");
+        // A group of its own, so that killing the phase kills what the shell started too
+        command.args(["-c", script]).process_group(0);
+        if !spec.limits.jvm_flags.is_empty() {
+            command.env("JAVA_TOOL_OPTIONS", spec.limits.jvm_flags.join(" "));
+        }
+
+        let (cpu_soft, cpu_hard) = cpu_rlimit(spec.limits.cpu_time_limit);
+        // SAFETY: setrlimit is async-signal-safe and nothing is allocated in the closure
+        unsafe {
+            command.pre_exec(move || {
+                setrlimit(Resource::RLIMIT_CPU, Some(cpu_soft), Some(cpu_hard))
+                    .map_err(|e| std::io::Error::from_raw_os_error(e as i32))
+            });
+        }
+
+        spawn_command(command, spec, stdin, stdout)
+    }
+
+    fn fingerprint(&self, spec: &ContainerSpec) -> Result<String, SimulatorError> {
+        Ok(self.commands.get(&spec.role).cloned().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fs,
+        io::{BufRead, BufReader, Read},
+        os::linux::process::ChildExt,
+        os::unix::process::ExitStatusExt,
+        process::Stdio,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use nix::libc;
+
+    use super::Native;
+    use crate::{
+        sandbox::{kill, ContainerSpec, SandboxBackend},
+        utils::example_spec,
+    };
+
+    fn spec(role: &str) -> ContainerSpec {
+        let mut spec = example_spec(role);
+        spec.limits.cpu_time_limit = 1;
+        spec.current_dir = Some("/tmp".to_owned());
+        spec
+    }
+
+    #[test]
+    fn spawns_host_process_with_pidfd() {
+        let native = Native::new(HashMap::from([(
+            "python_runner".to_owned(),
+            "pwd >&2".to_owned(),
+        )]));
+
+        let mut child = native
+            .spawn(&spec("python_runner"), Stdio::null(), Stdio::null())
+            .unwrap();
+        assert!(child.pidfd().is_ok());
+
+        let mut stderr = String::new();
+        child
+            .stderr
+            .take()
+            .unwrap()
+            .read_to_string(&mut stderr)
+            .unwrap();
+        assert_eq!(stderr.trim(), "/tmp");
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn cpu_time_limit_kills_busy_process() {
+        let native = Native::new(HashMap::from([(
+            "simulator".to_owned(),
+            "while :; do :; done".to_owned(),
+        )]));
+
+        let mut child = native
+            .spawn(&spec("simulator"), Stdio::null(), Stdio::null())
+            .unwrap();
+        let status = child.wait().unwrap();
+        assert!(!status.success());
+        assert_eq!(status.signal(), Some(libc::SIGXCPU));
+    }
+
+    #[test]
+    fn kill_stops_what_the_shell_started() {
+        let native = Native::new(HashMap::from([(
+            "simulator".to_owned(),
+            "sleep 30 & echo $! >&2; wait".to_owned(),
+        )]));
+
+        let mut child = native
+            .spawn(&spec("simulator"), Stdio::null(), Stdio::null())
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stderr.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let sleep = line.trim().to_owned();
+
+        kill(&mut child).unwrap();
+        child.wait().unwrap();
+        // Gone, or a zombie waiting for whoever reaps orphans
+        let stopped = || {
+            fs::read_to_string(format!("/proc/{sleep}/stat"))
+                .map_or(true, |stat| stat.contains(") Z "))
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !stopped() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(stopped());
+    }
+
+    #[test]
+    fn unknown_role_is_an_error() {
+        let native = Native::new(HashMap::new());
+        assert!(native
+            .spawn(&spec("cpp_runner"), Stdio::null(), Stdio::null())
+            .is_err());
+    }
+}
@@ -0,0 +1,445 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use log::warn;
+
+use crate::{
+    config::{DriverConfig, PoolConfig, RefillPolicy},
+    error::SimulatorError,
+};
+
+use super::{
+    cgroup_parent_args, lifecycle::POOL_PREFIX, remove_cgroup_parent, run_args, ContainerSpec,
+    FIFO_MOUNT,
+};
+
+/// Idle, already created runner and simulator containers for the docker compatible CLIs.
+/// Compilers write their output back through the game directory mount, so they always
+/// start cold.
+///
+/// Containers are created with the limits of their role, so a checkout only needs to `cp` the
+/// mounts of the spec in and `start` the container. Every container serves exactly one game
+/// and is removed by the backend that started it, like a cold one.
+pub struct WarmPool {
+    cli: &'static str,
+    /// Extra `create` arguments of the backend, e.g. `--userns=keep-id` for podman
+    cli_args: Vec<String>,
+    /// `CONTAINER_CGROUP_PARENT`, see `super::cgroup_parent_args`
+    cgroup_parent: Option<String>,
+    config: PoolConfig,
+    templates: HashMap<String, ContainerSpec>,
+    idle: Mutex<HashMap<String, VecDeque<String>>>,
+    filling: Mutex<()>,
+    /// Set by every `fill`, so that the one holding `filling` goes over all roles again
+    refill_requested: AtomicBool,
+    next_id: AtomicU64,
+}
+
+impl WarmPool {
+    pub fn new(
+        cli: &'static str,
+        cli_args: Vec<String>,
+        cgroup_parent: Option<String>,
+        config: PoolConfig,
+        templates: HashMap<String, ContainerSpec>,
+    ) -> Self {
+        WarmPool {
+            cli,
+            cli_args,
+            cgroup_parent,
+            config,
+            templates,
+            idle: Mutex::new(HashMap::new()),
+            filling: Mutex::new(()),
+            refill_requested: AtomicBool::new(false),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Builds and starts filling the pool, `None` when the backend or config disables it
+    pub fn from_config(config: &DriverConfig) -> Option<Arc<WarmPool>> {
+        let (cli, cli_args) = config.sandbox.cli()?;
+        if config.pool.sizes.values().all(|size| *size == 0) {
+            return None;
+        }
+        let templates = config
+            .pooled_roles()
+            .into_iter()
+            .filter_map(|role| Some((role.clone(), template(config, &role)?)))
+            .collect();
+
+        let pool = Arc::new(WarmPool::new(
+            cli,
+            cli_args,
+            config.container_cgroup_parent.clone(),
+            config.pool.clone(),
+            templates,
+        ));
+        pool.start();
+        Some(pool)
+    }
+
+    fn start(self: &Arc<Self>) {
+        let pool = Arc::clone(self);
+        match self.config.refill {
+            RefillPolicy::Immediate => {
+                thread::spawn(move || pool.fill());
+            }
+            RefillPolicy::Periodic => {
+                // Only a weak handle, so that the idle containers are removed on drop
+                let pool = Arc::downgrade(&pool);
+                let interval = Duration::from_secs(self.config.refill_interval);
+                thread::spawn(move || {
+                    while let Some(pool) = pool.upgrade() {
+                        pool.fill();
+                        drop(pool);
+                        thread::sleep(interval);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Whether a container of this pool can stand in for `spec`. FIFOs only work through
+    /// a real bind mount, so the simulator of a player-vs-player match always starts cold.
+    pub fn serves(&self, spec: &ContainerSpec) -> bool {
+        let live_mounts = spec.mounts.iter().any(|mount| mount.target == FIFO_MOUNT);
+        !live_mounts
+            && self.templates.get(&spec.role).is_some_and(|template| {
+                template.image == spec.image
+                    && template.limits == spec.limits
+                    && template.interactive == spec.interactive
+                    && template.command == spec.command
+            })
+    }
+
+    /// Takes an idle container for `spec`, renames it to `spec.name` and copies the
+    /// mounts of the spec into it. Returns the name the container was created as, or `None`
+    /// when the caller has to start cold.
+    pub fn checkout(self: &Arc<Self>, spec: &ContainerSpec) -> Option<String> {
+        if !self.serves(spec) {
+            return None;
+        }
+        let container = self
+            .idle
+            .lock()
+            .unwrap()
+            .get_mut(&spec.role)
+            .and_then(VecDeque::pop_front);
+
+        if self.config.refill == RefillPolicy::Immediate {
+            let pool = Arc::clone(self);
+            thread::spawn(move || pool.fill());
+        }
+
+        let container = container?;
+        match self.prepare(&container, spec) {
+            Ok(()) => Some(container),
+            Err(e) => {
+                warn!("Unable to use pooled container for {}: {e:?}", spec.name);
+                self.remove(&container);
+                self.remove(&spec.name);
+                remove_cgroup_parent(self.cgroup_parent.as_deref(), &container);
+                None
+            }
+        }
+    }
+
+    /// Arguments to start a checked out container with the FIFOs attached
+    pub fn start_args(spec: &ContainerSpec) -> Vec<String> {
+        let mut args = vec!["start".to_owned(), "-a".to_owned()];
+        if spec.interactive {
+            args.push("-i".to_owned());
+        }
+        args.push(spec.name.clone());
+        args
+    }
+
+    fn prepare(&self, container: &str, spec: &ContainerSpec) -> Result<(), SimulatorError> {
+        self.cli(&["rename", container, &spec.name])?;
+        for mount in &spec.mounts {
+            // `dir/.` copies the contents of a directory, like a bind mount would show them
+            let source = if mount.source.ends_with('/') {
+                format!("{}.", mount.source)
+            } else {
+                mount.source.clone()
+            };
+            let target = format!("{}:{}", spec.name, mount.target);
+            self.cli(&["cp", &source, &target])?;
+        }
+        Ok(())
+    }
+
+    /// Creates containers until every role is back at its configured size
+    pub fn fill(&self) {
+        self.refill_requested.store(true, Ordering::SeqCst);
+        // The flag is checked after releasing the guard, so a checkout during a fill in
+        // progress either gets its role refilled by that fill or takes the guard itself
+        while self.refill_requested.load(Ordering::SeqCst) {
+            let Ok(_guard) = self.filling.try_lock() else {
+                return;
+            };
+            self.refill_requested.store(false, Ordering::SeqCst);
+            self.fill_roles();
+        }
+    }
+
+    fn fill_roles(&self) {
+        for (role, template) in &self.templates {
+            let size = self.config.sizes.get(role).copied().unwrap_or(0);
+            loop {
+                let idle = self.idle.lock().unwrap().get(role).map_or(0, VecDeque::len);
+                if idle >= size {
+                    break;
+                }
+                match self.create(template) {
+                    Ok(name) => self
+                        .idle
+                        .lock()
+                        .unwrap()
+                        .entry(role.clone())
+                        .or_default()
+                        .push_back(name),
+                    Err(e) => {
+                        warn!("Unable to refill the {role} pool: {e:?}");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn create(&self, template: &ContainerSpec) -> Result<String, SimulatorError> {
+        let name = format!(
+            "{POOL_PREFIX}{}_{}_{}",
+            template.role,
+            std::process::id(),
+            self.next_id.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut args = create_args(&ContainerSpec {
+            name: name.clone(),
+            ..template.clone()
+        });
+        args.extend(self.cli_args.iter().cloned());
+        args.extend(cgroup_parent_args(self.cgroup_parent.as_deref(), &name));
+        args.push(template.image.clone());
+        args.extend(template.command.iter().cloned());
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        self.cli(&args)?;
+        Ok(name)
+    }
+
+    fn remove(&self, container: &str) {
+        let _ = self.cli(&["rm", "-f", container]);
+    }
+
+    fn cli(&self, args: &[&str]) -> Result<(), SimulatorError> {
+        let out = Command::new(self.cli)
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|err| {
+                SimulatorError::UnidentifiedError(format!("Couldnt run {}: {err}", self.cli))
+            })?;
+        if !out.status.success() {
+            return Err(SimulatorError::UnidentifiedError(format!(
+                "{} {} failed: {}",
+                self.cli,
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&out.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WarmPool {
+    fn drop(&mut self) {
+        let idle = std::mem::take(&mut *self.idle.lock().unwrap());
+        for container in idle.values().flatten() {
+            self.remove(container);
+            remove_cgroup_parent(self.cgroup_parent.as_deref(), container);
+        }
+    }
+}
+
+/// `create` arguments for an idle container of the spec, up to (not including) the image
+pub fn create_args(spec: &ContainerSpec) -> Vec<String> {
+    let mut args = run_args(&ContainerSpec {
+        mounts: vec![],
+        ..spec.clone()
+    });
+    args[0] = "create".to_owned();
+    args
+}
+
+/// The spec every runner or simulator of the role is started with, apart from name and mounts
+fn template(config: &DriverConfig, role: &str) -> Option<ContainerSpec> {
+    let phase = match role {
+        "simulator" => &config.simulator,
+        _ => {
+            &config
+                .languages
+                .values()
+                .find(|language| language.runner_role() == role)?
+                .run
+        }
+    };
+    Some(ContainerSpec {
+        name: String::new(),
+        role: role.to_owned(),
+        image: phase.image.clone(),
+        mounts: vec![],
+        limits: phase.limits.clone(),
+        command: phase.command.clone(),
+        interactive: true,
+        current_dir: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, VecDeque},
+        fs,
+        os::unix::fs::PermissionsExt,
+        sync::Arc,
+        thread,
+        time::Duration,
+    };
+
+    use super::{create_args, WarmPool};
+    use crate::{
+        config::PoolConfig,
+        sandbox::{lifecycle::instance_id, ContainerSpec, Mount, FIFO_MOUNT},
+        utils::{example_spec, TestDir},
+    };
+
+    fn spec() -> ContainerSpec {
+        ContainerSpec {
+            mounts: vec![Mount::new("/tmp/1/run".to_owned(), "/player_code")],
+            ..example_spec("cpp_runner")
+        }
+    }
+
+    #[test]
+    fn idle_containers_have_no_mounts() {
+        assert_eq!(
+            create_args(&spec()),
+            vec![
+                "create",
+                "--memory=100m",
+                "--memory-swap=100m",
+                "--cpus=1",
+                "--ulimit",
+                "cpu=10:11",
+                "--label",
+                "codecharacter-driver",
+                "--label",
+                &format!("codecharacter-driver.instance={}", instance_id()),
+                "--name",
+                "1_cpp_runner",
+                "-i",
+            ]
+        );
+        assert_eq!(
+            WarmPool::start_args(&spec()),
+            vec!["start", "-a", "-i", "1_cpp_runner"]
+        );
+    }
+
+    #[test]
+    fn only_serves_matching_specs() {
+        let template = ContainerSpec {
+            name: String::new(),
+            mounts: vec![],
+            ..spec()
+        };
+        let pool = WarmPool::new(
+            "docker",
+            vec![],
+            None,
+            PoolConfig::default(),
+            HashMap::from([("cpp_runner".to_owned(), template)]),
+        );
+
+        assert!(pool.serves(&spec()));
+
+        let mut other_limits = spec();
+        other_limits.limits.memory = "200m".to_owned();
+        assert!(!pool.serves(&other_limits));
+
+        let mut other_role = spec();
+        other_role.role = "simulator".to_owned();
+        assert!(!pool.serves(&other_role));
+
+        let mut fifos = spec();
+        fifos
+            .mounts
+            .push(Mount::new("/tmp/1/fifos/".to_owned(), FIFO_MOUNT));
+        assert!(!pool.serves(&fifos));
+    }
+
+    #[test]
+    fn checkout_during_a_fill_is_refilled() {
+        // Every CLI call takes a while, so the checkout below lands while the fill creates the
+        // container of the other role
+        let dir = TestDir::new("pool_checkout_during_a_fill_is_refilled");
+        let cli = dir.join("cli");
+        fs::write(&cli, "#!/bin/sh\nsleep 0.2\nexit 0\n").unwrap();
+        fs::set_permissions(&cli, fs::Permissions::from_mode(0o755)).unwrap();
+        let cli: &'static str = Box::leak(cli.to_str().unwrap().to_owned().into_boxed_str());
+
+        let roles = ["cpp_runner", "simulator"];
+        let pool = Arc::new(WarmPool::new(
+            cli,
+            vec![],
+            None,
+            PoolConfig {
+                sizes: roles.map(|role| (role.to_owned(), 1)).into(),
+                ..PoolConfig::default()
+            },
+            roles
+                .map(|role| {
+                    let template = ContainerSpec {
+                        role: role.to_owned(),
+                        ..spec()
+                    };
+                    (role.to_owned(), template)
+                })
+                .into(),
+        ));
+        let idle = |pool: &WarmPool| {
+            roles.map(|role| pool.idle.lock().unwrap().get(role).map_or(0, VecDeque::len))
+        };
+
+        let filler = thread::spawn({
+            let pool = Arc::clone(&pool);
+            move || pool.fill()
+        });
+        while idle(&pool) == [0, 0] {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let filled = roles[idle(&pool).iter().position(|n| *n == 1).unwrap()];
+        assert!(pool
+            .idle
+            .lock()
+            .unwrap()
+            .get_mut(filled)
+            .and_then(VecDeque::pop_front)
+            .is_some());
+        // Returns at once, the running fill takes over
+        pool.fill();
+        filler.join().unwrap();
+
+        assert_eq!(idle(&pool), [1, 1]);
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use crossbeam_channel::{Receiver, Sender};
+use log::{info, warn};
+use nix::sys::{
+    eventfd::{eventfd, EfdFlags},
+    signal::{SigSet, Signal},
+};
+
+use crate::error::SimulatorError;
+
+/// Tells the consumer to stop taking games and, once the grace period is over, the running
+/// games to give up.
+///
+/// Aborting makes an eventfd readable for good, so every game watching it in its event loop
+/// (and every compiler being waited for) wakes up, however many there are.
+pub struct Shutdown {
+    requested: AtomicBool,
+    notify: Sender<()>,
+    requests: Receiver<()>,
+    aborted: AtomicBool,
+    abort: OwnedFd,
+}
+
+impl Shutdown {
+    pub fn new() -> Result<Self, SimulatorError> {
+        let fd = eventfd(0, EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK).map_err(|e| {
+            SimulatorError::UnidentifiedError(format!("Unable to create shutdown eventfd: {e}"))
+        })?;
+        let (notify, requests) = crossbeam_channel::bounded(1);
+        Ok(Shutdown {
+            requested: AtomicBool::new(false),
+            notify,
+            requests,
+            aborted: AtomicBool::new(false),
+            // SAFETY: the fd was just created and is owned by nothing else
+            abort: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Stop taking new games, the running ones keep going
+    pub fn request(&self) {
+        if !self.requested.swap(true, Ordering::SeqCst) {
+            let _ = self.notify.try_send(());
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Receives once a shutdown was requested
+    pub fn requests(&self) -> &Receiver<()> {
+        &self.requests
+    }
+
+    /// Stops every running game, each of them reports that the driver shut down
+    pub fn abort(&self) {
+        self.request();
+        if !self.aborted.swap(true, Ordering::SeqCst) {
+            let one = 1u64.to_ne_bytes();
+            if let Err(e) = nix::unistd::write(self.abort.as_raw_fd(), &one) {
+                warn!("Unable to abort running games: {e}");
+            }
+        }
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    /// Readable once the running games have to give up
+    pub fn abort_fd(&self) -> RawFd {
+        self.abort.as_raw_fd()
+    }
+
+    /// Handles `SIGTERM` and `SIGINT` on a thread of its own: the first one requests a
+    /// shutdown, a second one aborts the running games right away.
+    ///
+    /// Has to be called before any other thread is started, the signals are blocked in the
+    /// calling thread and every thread it starts inherits that. Spawned processes get an
+    /// empty signal mask again.
+    pub fn listen(self: &Arc<Self>) -> Result<(), SimulatorError> {
+        let mut signals = SigSet::empty();
+        signals.add(Signal::SIGTERM);
+        signals.add(Signal::SIGINT);
+        signals.thread_block().map_err(|e| {
+            SimulatorError::UnidentifiedError(format!("Unable to block shutdown signals: {e}"))
+        })?;
+
+        let shutdown = Arc::clone(self);
+        thread::spawn(move || loop {
+            match signals.wait() {
+                Ok(signal) if shutdown.is_requested() => {
+                    info!("Received {signal} again, stopping running games");
+                    shutdown.abort();
+                }
+                Ok(signal) => {
+                    info!("Received {signal}, shutting down");
+                    shutdown.request();
+                }
+                Err(e) => {
+                    warn!("Unable to wait for shutdown signals: {e}");
+                    return;
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::poll::{poll, PollFd, PollFlags};
+
+    use super::Shutdown;
+
+    #[test]
+    fn request_then_abort() {
+        let shutdown = Shutdown::new().unwrap();
+        let readable = |fd| poll(&mut [PollFd::new(fd, PollFlags::POLLIN)], 0).unwrap() == 1;
+
+        assert!(shutdown.requests().try_recv().is_err());
+        shutdown.request();
+        shutdown.request();
+        assert!(shutdown.is_requested());
+        assert!(shutdown.requests().try_recv().is_ok());
+        assert!(!shutdown.is_aborted());
+        assert!(!readable(shutdown.abort_fd()));
+
+        shutdown.abort();
+        assert!(shutdown.is_aborted());
+        // Stays readable for every game that polls it
+        assert!(readable(shutdown.abort_fd()));
+        assert!(readable(shutdown.abort_fd()));
+    }
+}
@@ -1,5 +1,4 @@
 use std::{
-    env,
     fs::File,
     io::{BufWriter, Write},
 };
@@ -13,12 +12,12 @@ pub fn copy_dir_all(
     dst: impl AsRef<std::path::Path>,
 ) -> std::io::Result<()> {
     let opt = CopyOptions::new();
-    for entry in std::fs::read_dir(src).unwrap() {
+    for entry in std::fs::read_dir(src)? {
         let entry = entry?;
         let ty = entry.file_type()?;
         if ty.is_dir() {
             fs_extra::dir::copy(entry.path(), dst.as_ref(), &opt)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("{e}")))?;
+                .map_err(|e| std::io::Error::other(format!("{e}")))?;
         } else {
             std::fs::copy(entry.path(), dst.as_ref().join(entry.file_name()))?;
         }
@@ -27,7 +26,19 @@ pub fn copy_dir_all(
     Ok(())
 }
 
-pub fn send_initial_input(fifos: Vec<&File>, game_request: &GameRequest) {
+/// Tells the simulator of a player-vs-player match which participant FIFOs to open: `PVP <n>`
+/// and one tag per line, see `request::GameRequest` for the whole protocol
+pub fn send_participants(fifo: &File, tags: &[String]) {
+    let mut writer = BufWriter::new(fifo);
+    writer
+        .write_all(format!("PVP {}\n", tags.len()).as_bytes())
+        .unwrap();
+    for tag in tags {
+        writer.write_all(format!("{tag}\n").as_bytes()).unwrap();
+    }
+}
+
+pub fn send_initial_input(fifos: Vec<&File>, game_request: &GameRequest, map_size: u32) {
     let game_parameters = &game_request.parameters;
     for fifo in fifos {
         let mut writer = BufWriter::new(fifo);
@@ -78,7 +89,6 @@ pub fn send_initial_input(fifos: Vec<&File>, game_request: &GameRequest) {
                 )
                 .unwrap();
         }
-        let map_size: u32 = env::var("MAP_SIZE").unwrap().parse().unwrap();
         writer
             .write_all(format!("{map_size} {map_size}\n").as_bytes())
             .unwrap();
@@ -95,6 +105,7 @@ pub fn make_copy(
     src_dir: &str,
     dest_dir: &str,
     player_code_file: &str,
+    source_code: &str,
     game_request: &GameRequest,
 ) -> Option<response::GameStatus> {
     if let Err(e) = copy_dir_all(src_dir, dest_dir) {
@@ -107,7 +118,7 @@ pub fn make_copy(
     }
 
     if let Err(e) = std::fs::File::create(player_code_file).and_then(|mut file| {
-        file.write_all(game_request.source_code.as_bytes())
+        file.write_all(source_code.as_bytes())
             .and_then(|_| file.sync_all())
     }) {
         return Some(create_error_response(
@@ -117,3 +128,57 @@ pub fn make_copy(
     }
     None
 }
+
+/// A fresh directory for one test, named after the test and the process so that concurrent
+/// test runs never share it, and removed when dropped
+#[cfg(test)]
+pub(crate) struct TestDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    pub(crate) fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("cc_{test}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TestDir(dir)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDir {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The spec of a `1_{role}` container with 1 cpu, 100m of memory and no swap, a 10 second CPU
+/// time limit and no mounts, for tests to adjust
+#[cfg(test)]
+pub(crate) fn example_spec(role: &str) -> crate::sandbox::ContainerSpec {
+    crate::sandbox::ContainerSpec {
+        name: format!("1_{role}"),
+        role: role.to_owned(),
+        image: role.replace('_', "-"),
+        mounts: vec![],
+        limits: crate::config::ResourceProfile {
+            cpus: 1.0,
+            memory: "100m".to_owned(),
+            memory_swap: "100m".to_owned(),
+            cpu_time_limit: 10,
+            wall_time_limit: None,
+            jvm_flags: vec![],
+        },
+        command: vec![],
+        interactive: true,
+        current_dir: None,
+    }
+}